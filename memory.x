@@ -1,6 +1,10 @@
 MEMORY {
     BOOT2 : ORIGIN = 0x10000000, LENGTH = 0x100
-    FLASH : ORIGIN = 0x10000100, LENGTH = 2048K - 0x100
+    FLASH : ORIGIN = 0x10000100, LENGTH = 2048K - 0x100 - 72K
+    /* Message history, see Rp2040Flash::history in src/store.rs */
+    HISTORY : ORIGIN = 0x10000000 + 2048K - 72K, LENGTH = 64K
+    /* Settings store, see Rp2040Flash::settings in src/store.rs */
+    SETTINGS : ORIGIN = 0x10000000 + 2048K - 8K, LENGTH = 8K
     RAM   : ORIGIN = 0x20000000, LENGTH = 256K
 }
 
@@ -0,0 +1,204 @@
+//! Time a LoRa frame occupies the air.
+//!
+//! Follows the formula of the Semtech SX1276 datasheet, section 4.1.1.7:
+//!
+//! ```text
+//! symbol   = 2^SF / BW
+//! preamble = (n_preamble + 4.25) symbols
+//! payload  = 8 + max(ceil((8PL - 4SF + 28 + 16CRC - 20IH) / 4(SF - 2DE)) (CR + 4), 0) symbols
+//! ```
+//! where `DE` is set when low data rate optimisation is on, which the modem
+//! needs when symbols last 16 ms or more.
+#![allow(dead_code)]
+
+use radio_sx127x::device::lora::{
+    Bandwidth, CodingRate, LoRaChannel, LoRaConfig, PayloadCrc, PayloadLength, SpreadingFactor,
+};
+
+use crate::crypto;
+use crate::fragment::{self, FRAGMENT_HEADER, MAX_CHUNK};
+use crate::frame::HEADER_LEN;
+
+/// Symbols this long or longer need low data rate optimisation.
+pub const LOW_DATA_RATE_SYMBOL_US: u32 = 16_000;
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct TimeOnAir {
+    /// Symbols of the preamble, configured length plus 4.25, in quarters.
+    pub preamble_quarters: u32,
+    /// Symbols after the preamble, header and CRC included.
+    pub payload_symbols: u32,
+    pub symbol_us: u32,
+    pub low_data_rate: bool,
+    /// Whole frame, preamble included.
+    pub micros: u32,
+}
+
+impl TimeOnAir {
+    /// Symbols of the whole frame, the preamble's quarter rounded up.
+    pub fn symbols(&self) -> u32 {
+        (self.preamble_quarters + 3) / 4 + self.payload_symbols
+    }
+
+    /// Airtime in milliseconds, rounded up.
+    pub fn millis(&self) -> u32 {
+        (self.micros + 999) / 1_000
+    }
+}
+
+pub fn spreading_factor(sf: SpreadingFactor) -> u32 {
+    match sf {
+        SpreadingFactor::Sf6 => 6,
+        SpreadingFactor::Sf7 => 7,
+        SpreadingFactor::Sf8 => 8,
+        SpreadingFactor::Sf9 => 9,
+        SpreadingFactor::Sf10 => 10,
+        SpreadingFactor::Sf11 => 11,
+        SpreadingFactor::Sf12 => 12,
+    }
+}
+
+pub fn bandwidth_hz(bw: Bandwidth) -> u32 {
+    match bw {
+        Bandwidth::Bw125kHz => 125_000,
+        Bandwidth::Bw250kHz => 250_000,
+        Bandwidth::Bw500kHz => 500_000,
+    }
+}
+
+/// `n` of the 4/(4 + n) coding rate.
+pub fn coding_rate(cr: CodingRate) -> u32 {
+    match cr {
+        CodingRate::Cr4_5 => 1,
+        CodingRate::Cr4_6 => 2,
+        CodingRate::Cr4_7 => 3,
+        CodingRate::Cr4_8 => 4,
+    }
+}
+
+/// Duration of one symbol in microseconds.
+pub fn symbol_us(channel: &LoRaChannel) -> u32 {
+    ((1u64 << spreading_factor(channel.sf)) * 1_000_000 / bandwidth_hz(channel.bw) as u64) as u32
+}
+
+/// `true` when the channel needs low data rate optimisation.
+pub fn low_data_rate(channel: &LoRaChannel) -> bool {
+    symbol_us(channel) >= LOW_DATA_RATE_SYMBOL_US
+}
+
+/// Airtime and symbol count of a frame carrying `len` bytes.
+///
+/// 10 bytes with an explicit header and CRC at SF7, 125 kHz, 4/5 and a
+/// preamble of 8 take 12.25 + 28 symbols of 1.024 ms, that is 41.216 ms.
+pub fn time_on_air(channel: &LoRaChannel, config: &LoRaConfig, len: usize) -> TimeOnAir {
+    let sf = spreading_factor(channel.sf);
+    let bw = bandwidth_hz(channel.bw);
+    let cr = coding_rate(channel.cr);
+    let low_data_rate = low_data_rate(channel);
+    let crc = matches!(config.payload_crc, PayloadCrc::Enabled);
+    let implicit = matches!(config.payload_len, PayloadLength::Constant(_));
+
+    let bits = 8 * len as i32 - 4 * sf as i32 + 28 + if crc { 16 } else { 0 }
+        - if implicit { 20 } else { 0 };
+    let per_block = 4 * (sf as i32 - if low_data_rate { 2 } else { 0 });
+    let blocks = if bits > 0 {
+        (bits + per_block - 1) / per_block
+    } else {
+        0
+    };
+    let payload_symbols = 8 + blocks as u32 * (cr + 4);
+    let preamble_quarters = 4 * config.preamble_len as u32 + 17;
+    let quarters = (preamble_quarters + 4 * payload_symbols) as u64;
+    TimeOnAir {
+        preamble_quarters,
+        payload_symbols,
+        symbol_us: symbol_us(channel),
+        low_data_rate,
+        micros: ((quarters << sf) * 1_000_000 / (4 * bw as u64)) as u32,
+    }
+}
+
+/// Airtime of a frame in milliseconds, rounded up.
+pub fn time_on_air_ms(channel: &LoRaChannel, config: &LoRaConfig, len: usize) -> u32 {
+    time_on_air(channel, config, len).millis()
+}
+
+/// Airtime in milliseconds of a sealed `len` bytes message, counting the
+/// frame headers and the fragments it is split in.
+pub fn message_ms(channel: &LoRaChannel, config: &LoRaConfig, len: usize) -> u32 {
+    let overhead = HEADER_LEN + crypto::OVERHEAD;
+    if fragment::count(len) == 1 {
+        return time_on_air_ms(channel, config, overhead + len);
+    }
+    (0..len)
+        .step_by(MAX_CHUNK)
+        .map(|start| {
+            let chunk = (len - start).min(MAX_CHUNK);
+            time_on_air_ms(channel, config, overhead + FRAGMENT_HEADER + chunk)
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fragment::MAX_SINGLE;
+    use crate::sx127x::{CONFIG_CH, CONFIG_LORA};
+
+    fn channel(sf: SpreadingFactor) -> LoRaChannel {
+        LoRaChannel {
+            sf,
+            cr: CodingRate::Cr4_5,
+            ..CONFIG_CH
+        }
+    }
+
+    #[test]
+    fn datasheet_example() {
+        let air = time_on_air(&channel(SpreadingFactor::Sf7), &CONFIG_LORA, 10);
+        assert_eq!(air.symbol_us, 1_024);
+        assert!(!air.low_data_rate);
+        assert_eq!(air.preamble_quarters, 49);
+        assert_eq!(air.payload_symbols, 28);
+        assert_eq!(air.symbols(), 41);
+        assert_eq!(air.micros, 41_216);
+        assert_eq!(air.millis(), 42);
+    }
+
+    #[test]
+    fn long_symbols_use_low_data_rate() {
+        let air = time_on_air(&channel(SpreadingFactor::Sf12), &CONFIG_LORA, 10);
+        assert_eq!(air.symbol_us, 32_768);
+        assert!(air.low_data_rate);
+        assert_eq!(air.payload_symbols, 18);
+        assert_eq!(air.micros, 991_232);
+    }
+
+    #[test]
+    fn implicit_header_and_no_crc() {
+        let implicit = LoRaConfig {
+            payload_len: PayloadLength::Constant(10),
+            ..CONFIG_LORA
+        };
+        let air = time_on_air(&channel(SpreadingFactor::Sf7), &implicit, 10);
+        assert_eq!(air.payload_symbols, 23);
+        assert_eq!(air.micros, 36_096);
+        // an empty frame without CRC is only the 8 symbols of the header
+        let bare = LoRaConfig {
+            payload_crc: PayloadCrc::Disabled,
+            ..CONFIG_LORA
+        };
+        let air = time_on_air(&channel(SpreadingFactor::Sf9), &bare, 0);
+        assert_eq!(air.payload_symbols, 8);
+        assert_eq!(air.micros, 82_944);
+    }
+
+    #[test]
+    fn messages_add_up_their_fragments() {
+        let ch = channel(SpreadingFactor::Sf7);
+        assert_eq!(message_ms(&ch, &CONFIG_LORA, MAX_SINGLE), 400);
+        // 219 and 81 bytes in two fragments
+        assert_eq!(message_ms(&ch, &CONFIG_LORA, 300), 400 + 195);
+    }
+}
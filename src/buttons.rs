@@ -1,16 +1,15 @@
 #![no_std]
 #![no_main]
-mod blink;
-mod input;
 mod stuff;
 
 use bsp::{entry, hal::gpio::FunctionSpi};
 use defmt::*;
 use defmt_rtt as _;
+use embedded_hal_02::adc::OneShot;
 use embedded_hal_compat::eh0_2::digital::v2::InputPin;
-use embedded_hal_compat::eh0_2::spi::{Mode, Phase, Polarity};
 use embedded_hal_compat::ForwardCompat;
 use fugit::RateExtU32;
+use lora_rust::sx127x::CONFIG_RADIO;
 use panic_probe as _;
 use stuff::*;
 
@@ -20,6 +19,7 @@ use rp_pico as bsp;
 // use sparkfun_pro_micro_rp2040 as bsp;
 
 use bsp::hal::{
+    adc::Adc,
     clocks::{init_clocks_and_plls, Clock},
     pac,
     sio::Sio,
@@ -27,72 +27,22 @@ use bsp::hal::{
     watchdog::Watchdog,
 };
 
-use radio_sx127x::Error as sx127xError;
-use radio_sx127x::{
-    device::lora::{
-        Bandwidth, CodingRate, FrequencyHopping, LoRaChannel, LoRaConfig, PayloadCrc,
-        PayloadLength, SpreadingFactor,
-    },
-    device::{Channel, Modem, PaConfig, PaSelect},
-    prelude::*, // prelude has Sx127x,
-}; // Error name conflict with hals
+use radio_sx127x::prelude::*; // prelude has Sx127x
 
-use radio::{Receive, Transmit};
+use lora_rust::interface::{Delivery, Interface};
+use lora_rust::neighbours::{battery_percent, vsys_millivolts};
+use lora_rust::node::Node;
+use lora_rust::settings;
+use lora_rust::state::{reached, Outbox, Outgoing, Packet, State};
+use lora_rust::store::{Rp2040Flash, Store};
 
-use crate::input::Button2;
+use lora_rust::input::Button2;
 
 //use hal::spidev::{self, SpidevOptions};
 //use hal::sysfs_gpio::Direction;
 //use bsp::hal::Delay;
 //use hal::{Pin, Spidev};
 
-pub const MODE: Mode = Mode {
-    //  SPI mode for radio
-    phase: Phase::CaptureOnSecondTransition,
-    polarity: Polarity::IdleHigh,
-};
-
-pub const FREQUENCY: u32 = 433_400_000; // frequency in hertz ch_12: 915_000_000, ch_2: 907_400_000
-
-pub const CONFIG_CH: LoRaChannel = LoRaChannel {
-    freq: FREQUENCY as u32, // frequency in hertz
-    bw: Bandwidth::Bw125kHz,
-    sf: SpreadingFactor::Sf7,
-    cr: CodingRate::Cr4_8,
-};
-
-pub const CONFIG_LORA: LoRaConfig = LoRaConfig {
-    preamble_len: 0x8,
-    symbol_timeout: 0x64,
-    payload_len: PayloadLength::Variable,
-    payload_crc: PayloadCrc::Enabled,
-    frequency_hop: FrequencyHopping::Disabled,
-    invert_iq: false,
-};
-
-//   compare other settings in python version
-//    lora.set_mode(sx127x_lora::RadioMode::Stdby).unwrap();
-//    set_tx_power(level, output_pin) level >17 => PA_BOOST.
-//    lora.set_tx_power(17,1).unwrap();
-//    lora.set_tx_power(15,1).unwrap();
-
-//baud = 1000000 is this needed for spi or just USART ?
-
-pub const CONFIG_PA: PaConfig = PaConfig {
-    output: PaSelect::Boost,
-    power: 1,
-};
-
-//let CONFIG_RADIO = Config::default() ;
-
-pub const CONFIG_RADIO: radio_sx127x::device::Config = radio_sx127x::device::Config {
-    modem: Modem::LoRa(CONFIG_LORA),
-    channel: Channel::LoRa(CONFIG_CH),
-    pa_config: CONFIG_PA,
-    xtal_freq: 32000000, // CHECK
-    timeout_ms: 100,
-};
-
 #[entry]
 fn main() -> ! {
     info!("Program start");
@@ -160,94 +110,62 @@ fn main() -> ! {
     }
     let mut _cursor = 0;
     let mut button = Button2::new(pins.gpio19.into_pull_up_input());
-    let mut state = stuff::State::Init;
+    let timer = bsp::hal::Timer::new(pac.TIMER, &mut pac.RESETS);
+    let mut adc = Adc::new(pac.ADC, &mut pac.RESETS);
+    let mut vsys = pins.voltage_monitor.into_floating_input();
+    let mut state = State::Init;
+    let mut outbox: Outbox<1> = Outbox::new();
+    let mut node = Node::new(NODE_ADDRESS);
+    node.key = CHANNEL_KEY;
+    if !NODE_NAME.is_empty() {
+        node.set_name(NODE_NAME);
+    }
+    let mut store = Store::mount(Rp2040Flash::settings());
+    settings::renew_epoch(&mut store, &mut node, &mut Console);
 
     loop {
-        state = match state.run_state(&mut lora, &mut button) {
-            Err(stuff::Error::Radio(e)) => match e {
-                sx127xError::Hal(_) => crate::panic!("HAL problem"),
-                sx127xError::InvalidConfiguration => crate::panic!("invalid Configuration"),
-                sx127xError::Aborted => {
-                    info!("Transaction aborted");
-                    State::PrepareIdle
-                }
-                sx127xError::InvalidResponse => {
-                    info!("Invalid response");
-                    State::Reset
-                }
-                sx127xError::Timeout => {
-                    info!("Timeout");
-                    State::Reset
-                }
-                sx127xError::Crc => State::PrepareIdle,
-                sx127xError::BufferSize => State::PrepareIdle,
-                sx127xError::InvalidDevice(_) => {
-                    info!("invalid device, restarting");
-                    State::Reset
-                }
-            },
-            Ok(state) => state,
+        if button.just_pressed() && outbox.is_empty() {
+            _ = outbox.push_back(Outgoing::broadcast(
+                Packet::from_slice(b"Kikooo\n UWU ").unwrap(),
+            ));
+        }
+        let now = (timer.get_counter() / 1_000) as u32;
+        if reached(now, node.beacon_at) {
+            // measured for the beacon about to go out
+            let raw: Option<u16> = adc.read(&mut vsys).ok();
+            node.presence.battery = raw.and_then(|raw| battery_percent(vsys_millivolts(raw)));
+        }
+        if node.epoch_spent() {
+            // the next epoch is reserved before any frame is sealed in it
+            settings::renew_epoch(&mut store, &mut node, &mut Console);
         }
+        state = match state.run_state(&mut lora, &mut node, &mut outbox, &mut Console, now) {
+            Err(e) => state.on_error(e, &mut node, now),
+            Ok(state) => state,
+        };
     }
 }
 
-use core::fmt::Debug;
-
-impl stuff::State {
-    fn run_state<Hal: radio_sx127x::base::Hal, P: InputPin, T: Debug + 'static>(
-        &self,
-        lora: &mut radio_sx127x::Sx127x<Hal>,
-        button: &mut Button2<P>,
-    ) -> Result<Self, stuff::Error<T>>
-    where
-        stuff::Error<T>: From<sx127xError<T>>,
-        P::Error: Debug,
-        stuff::Error<T>: From<radio_sx127x::Error<<Hal as radio_sx127x::base::Hal>::Error>>,
-    {
-        match self {
-            State::Init => {
-                info!("init");
-                Ok(State::PrepareIdle)
-            }
-            State::Reset => {
-                crate::panic!("reset unimplemented")
-            }
-            State::PrepareIdle => {
-                lora.start_receive()?;
-
-                Ok(State::Idle)
-            }
-            State::Idle => {
-                if button.just_pressed() {
-                    info!("Send packet");
-                    lora.start_transmit(b"Kikooo\n UWU ")?;
-                    Ok(State::Sending)
-                } else {
-                    match lora.check_receive(false)? {
-                        true => Ok(State::Received), //have a valid packet in the buffer
-                        false => Ok(State::Idle),    //got an invalid packet
-                    }
-                }
-            }
-            State::Sending => match lora.check_transmit()? {
-                true => Ok(State::SendingDone),
-                false => Ok(State::Sending),
-            },
-            State::Received => {
-                let mut buff = [0u8; 256];
-                let (len, info) = lora.get_received(&mut buff)?;
-                info!(
-                    "received packet len = {} info : {} {}",
-                    len, info.rssi, info.snr
-                );
-                //Ok(Self::Idle)
-                lora.start_transmit(&buff[..len])?;
-                Ok(State::Sending)
-            }
-            State::SendingDone => {
-                lora.start_receive()?;
-                Ok(Self::Idle)
-            }
-        }
+/// Interface that only reports through defmt, this board has no screen.
+struct Console;
+
+impl Interface for Console {
+    fn set_title(&mut self, _title: &[u8]) {}
+    fn set_input(&mut self, _input: &[u8], _cursor: usize) {}
+    fn set_overlay(&mut self, _overlay: Option<&'static str>) {}
+    fn add_own(&mut self, id: u16, _to: Option<&[u8]>, _body: &[u8], _status: Delivery) {
+        info!("sent {}", id);
+    }
+    fn set_delivery(&mut self, id: u16, status: Delivery) {
+        info!("{} delivered: {}", id, status == Delivery::Delivered);
+    }
+    fn add_log(&mut self, from: Option<&[u8]>, body: &[u8], snr: Option<i16>, rssi: Option<i16>) {
+        info!(
+            "got {},{} from {=[u8]:a}:{=[u8]:a}",
+            rssi,
+            snr,
+            from.unwrap_or(b"?"),
+            body
+        );
     }
 }
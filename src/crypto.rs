@@ -0,0 +1,305 @@
+//! Authenticated encryption of frame payloads with a pre-shared channel key.
+//!
+//! Payloads are sealed with ChaCha20-Poly1305. The nonce is the sender
+//! address followed by a 32 bit counter, made of an epoch sent in clear in
+//! front of the ciphertext and the frame sequence number:
+//!
+//! ```text
+//! nonce  = src (2) | epoch (2) | seq (2) | 0 (6)
+//! sealed = epoch (2) | ciphertext | tag (16)
+//! ```
+//! The header fields are authenticated as associated data, except `ttl`,
+//! `via` and `next` that relays rewrite.
+#![allow(dead_code)]
+
+use chacha20poly1305::{aead::AeadInPlace, ChaCha20Poly1305, KeyInit, Nonce, Tag};
+
+use crate::frame::{Address, Header};
+
+pub const KEY_LEN: usize = 32;
+pub const TAG_LEN: usize = 16;
+const EPOCH_LEN: usize = 2;
+/// Bytes added to a payload by [`seal`].
+pub const OVERHEAD: usize = EPOCH_LEN + TAG_LEN;
+
+pub type Key = [u8; KEY_LEN];
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CryptoError {
+    /// The output buffer cannot hold the result.
+    TooLong,
+    /// The frame was not sealed with our key or was tampered with.
+    Authentication,
+}
+
+fn nonce(src: Address, epoch: u16, seq: u16) -> [u8; 12] {
+    let mut nonce = [0u8; 12];
+    nonce[0..2].copy_from_slice(&src.to_le_bytes());
+    nonce[2..4].copy_from_slice(&epoch.to_le_bytes());
+    nonce[4..6].copy_from_slice(&seq.to_le_bytes());
+    nonce
+}
+
+fn associated_data(header: &Header) -> [u8; 8] {
+    let mut aad = [0u8; 8];
+    aad[0..2].copy_from_slice(&header.src.to_le_bytes());
+    aad[2..4].copy_from_slice(&header.dst.to_le_bytes());
+    aad[4..6].copy_from_slice(&header.seq.to_le_bytes());
+    aad[6] = header.kind as u8;
+    aad[7] = header.flags;
+    aad
+}
+
+/// Encrypt `payload` for `header` into `out`, returning the sealed length.
+pub fn seal(
+    key: &Key,
+    header: &Header,
+    epoch: u16,
+    payload: &[u8],
+    out: &mut [u8],
+) -> Result<usize, CryptoError> {
+    let len = payload.len() + OVERHEAD;
+    if out.len() < len {
+        return Err(CryptoError::TooLong);
+    }
+    out[..EPOCH_LEN].copy_from_slice(&epoch.to_le_bytes());
+    let body = &mut out[EPOCH_LEN..EPOCH_LEN + payload.len()];
+    body.copy_from_slice(payload);
+    let tag = ChaCha20Poly1305::new(key.into())
+        .encrypt_in_place_detached(
+            Nonce::from_slice(&nonce(header.src, epoch, header.seq)),
+            &associated_data(header),
+            body,
+        )
+        .map_err(|_| CryptoError::TooLong)?;
+    out[EPOCH_LEN + payload.len()..len].copy_from_slice(&tag);
+    Ok(len)
+}
+
+/// Check and decrypt a payload sealed for `header`, the plaintext goes to `out`.
+/// Returns it along with the epoch the sender sealed it with.
+pub fn open<'a>(
+    key: &Key,
+    header: &Header,
+    sealed: &[u8],
+    out: &'a mut [u8],
+) -> Result<(u16, &'a [u8]), CryptoError> {
+    if sealed.len() < OVERHEAD {
+        return Err(CryptoError::Authentication);
+    }
+    let epoch = u16::from_le_bytes([sealed[0], sealed[1]]);
+    let (body, tag) = sealed[EPOCH_LEN..].split_at(sealed.len() - OVERHEAD);
+    let out = out.get_mut(..body.len()).ok_or(CryptoError::TooLong)?;
+    out.copy_from_slice(body);
+    ChaCha20Poly1305::new(key.into())
+        .decrypt_in_place_detached(
+            Nonce::from_slice(&nonce(header.src, epoch, header.seq)),
+            &associated_data(header),
+            out,
+            Tag::from_slice(tag),
+        )
+        .map_err(|_| CryptoError::Authentication)?;
+    Ok((epoch, out))
+}
+
+/// Epoch a payload was sealed in, readable without the key.
+pub fn epoch(sealed: &[u8]) -> Option<u16> {
+    match sealed {
+        [low, high, ..] => Some(u16::from_le_bytes([*low, *high])),
+        _ => None,
+    }
+}
+
+/// Parse a key written as 64 hexadecimal digits, usable in constants.
+pub const fn parse_key(s: &str) -> Option<Key> {
+    let bytes = s.as_bytes();
+    if bytes.len() != KEY_LEN * 2 {
+        return None;
+    }
+    let mut key = [0u8; KEY_LEN];
+    let mut i = 0;
+    while i < bytes.len() {
+        let digit = match bytes[i] {
+            b'0'..=b'9' => bytes[i] - b'0',
+            b'a'..=b'f' => bytes[i] - b'a' + 10,
+            b'A'..=b'F' => bytes[i] - b'A' + 10,
+            _ => return None,
+        };
+        key[i / 2] = (key[i / 2] << 4) | digit;
+        i += 1;
+    }
+    Some(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::{Kind, BROADCAST, FLAG_ACK, FLAG_ENCRYPTED};
+
+    const KEY: Key =
+        match parse_key("808182838485868788898a8b8c8d8e8f909192939495969798999a9b9c9d9e9f") {
+            Some(key) => key,
+            None => panic!(),
+        };
+
+    fn header() -> Header {
+        Header::new(0x1234, BROADCAST, 7, Kind::Chat, FLAG_ACK | FLAG_ENCRYPTED)
+    }
+
+    #[test]
+    fn chacha20_poly1305_rfc8439_vector() {
+        // RFC 8439 section 2.8.2
+        let nonce = [
+            0x07, 0, 0, 0, 0x40, 0x41, 0x42, 0x43, 0x44, 0x45, 0x46, 0x47,
+        ];
+        let aad = [
+            0x50, 0x51, 0x52, 0x53, 0xc0, 0xc1, 0xc2, 0xc3, 0xc4, 0xc5, 0xc6, 0xc7,
+        ];
+        let mut text = *b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.";
+        let tag = ChaCha20Poly1305::new(&KEY.into())
+            .encrypt_in_place_detached(Nonce::from_slice(&nonce), &aad, &mut text)
+            .unwrap();
+        assert_eq!(
+            text[..16],
+            [
+                0xd3, 0x1a, 0x8d, 0x34, 0x64, 0x8e, 0x60, 0xdb, 0x7b, 0x86, 0xaf, 0xbc, 0x53, 0xef,
+                0x7e, 0xc2
+            ]
+        );
+        assert_eq!(
+            tag[..],
+            [
+                0x1a, 0xe1, 0x0b, 0x59, 0x4f, 0x09, 0xe2, 0x6a, 0x7e, 0x90, 0x2e, 0xcb, 0xd0, 0x60,
+                0x06, 0x91
+            ]
+        );
+    }
+
+    #[test]
+    fn nonce_and_associated_data_layout() {
+        assert_eq!(
+            nonce(0x1234, 0x0102, 7),
+            [0x34, 0x12, 0x02, 0x01, 0x07, 0, 0, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(
+            associated_data(&header()),
+            [0x34, 0x12, 0xff, 0xff, 0x07, 0x00, 0x00, 0x03]
+        );
+    }
+
+    #[test]
+    fn seal_known_answer() {
+        let mut out = [0u8; 32];
+        let len = seal(&KEY, &header(), 0x0102, b"hello", &mut out).unwrap();
+        assert_eq!(len, 5 + OVERHEAD);
+        assert_eq!(
+            out[..len],
+            [
+                0x02, 0x01, 0x1a, 0x23, 0xa8, 0xa7, 0xa0, 0x2f, 0x6b, 0xcd, 0x45, 0x61, 0x56, 0x1b,
+                0x6b, 0xd1, 0xb7, 0x11, 0x2f, 0x49, 0x20, 0x01, 0x13
+            ]
+        );
+        let mut plain = [0u8; 32];
+        assert_eq!(
+            open(&KEY, &header(), &out[..len], &mut plain),
+            Ok((0x0102, &b"hello"[..]))
+        );
+    }
+
+    #[test]
+    fn tampering_is_detected() {
+        let mut sealed = [0u8; 32];
+        let len = seal(&KEY, &header(), 3, b"hello", &mut sealed).unwrap();
+        let mut plain = [0u8; 32];
+        for i in 0..len {
+            let mut copy = sealed;
+            copy[i] ^= 0x40;
+            assert_eq!(
+                open(&KEY, &header(), &copy[..len], &mut plain),
+                Err(CryptoError::Authentication),
+                "byte {}",
+                i
+            );
+        }
+        let mut other = KEY;
+        other[0] ^= 1;
+        assert_eq!(
+            open(&other, &header(), &sealed[..len], &mut plain),
+            Err(CryptoError::Authentication)
+        );
+        let forged = [
+            Header {
+                src: 0x1235,
+                ..header()
+            },
+            Header {
+                dst: 0x0001,
+                ..header()
+            },
+            Header { seq: 8, ..header() },
+            Header {
+                kind: Kind::Control,
+                ..header()
+            },
+            Header {
+                flags: FLAG_ENCRYPTED,
+                ..header()
+            },
+        ];
+        for forged in forged.iter() {
+            assert_eq!(
+                open(&KEY, forged, &sealed[..len], &mut plain),
+                Err(CryptoError::Authentication)
+            );
+        }
+    }
+
+    #[test]
+    fn relays_may_rewrite_the_path() {
+        let mut sealed = [0u8; 32];
+        let len = seal(&KEY, &header(), 3, b"hello", &mut sealed).unwrap();
+        let relayed = Header {
+            ttl: 1,
+            via: 0x5678,
+            next: 0x9abc,
+            ..header()
+        };
+        let mut plain = [0u8; 32];
+        assert_eq!(
+            open(&KEY, &relayed, &sealed[..len], &mut plain),
+            Ok((3, &b"hello"[..]))
+        );
+    }
+
+    #[test]
+    fn short_buffers() {
+        let mut out = [0u8; 5 + OVERHEAD - 1];
+        assert_eq!(
+            seal(&KEY, &header(), 0, b"hello", &mut out),
+            Err(CryptoError::TooLong)
+        );
+        let mut sealed = [0u8; 32];
+        let len = seal(&KEY, &header(), 0, b"hello", &mut sealed).unwrap();
+        let mut plain = [0u8; 4];
+        assert_eq!(
+            open(&KEY, &header(), &sealed[..len], &mut plain),
+            Err(CryptoError::TooLong)
+        );
+        assert_eq!(
+            open(&KEY, &header(), &sealed[..OVERHEAD - 1], &mut plain),
+            Err(CryptoError::Authentication)
+        );
+    }
+
+    #[test]
+    fn keys_in_hex() {
+        assert_eq!(KEY[0], 0x80);
+        assert_eq!(KEY[31], 0x9f);
+        let mixed = "AbCdabcdABCDabcdabcdabcdabcdabcdabcdabcdabcdabcdabcdabcdabcdabcd";
+        assert_eq!(parse_key(mixed).map(|key| key[1]), Some(0xcd));
+        assert_eq!(parse_key("80"), None);
+        let bad = "0g00000000000000000000000000000000000000000000000000000000000000";
+        assert_eq!(parse_key(bad), None);
+    }
+}
@@ -1,10 +1,10 @@
 #![no_std]
 #![no_main]
-mod blink;
-mod input;
 mod stuff;
 
 use embedded_graphics::text::renderer::TextRenderer;
+use embedded_hal_02::adc::OneShot;
+use lora_rust::sx127x::CONFIG_RADIO;
 use stuff::*;
 
 use bsp::{entry, hal::gpio::FunctionSpi};
@@ -35,6 +35,7 @@ use rp_pico as bsp;
 // use sparkfun_pro_micro_rp2040 as bsp;
 
 use bsp::hal::{
+    adc::Adc,
     clocks::{init_clocks_and_plls, Clock},
     pac,
     sio::Sio,
@@ -48,11 +49,15 @@ use bsp::hal::{
 //use hal::{Pin, Spidev};
 
 use radio_sx127x::prelude::*;
-use radio_sx127x::Error as sx127xError; // Error name conflict with hals
 
-use radio::{Receive, Transmit};
+use lora_rust::interface::{Delivery, Interface};
+use lora_rust::neighbours::{battery_percent, vsys_millivolts};
+use lora_rust::node::Node;
+use lora_rust::settings;
+use lora_rust::state::{reached, Outbox, Outgoing, Packet, State};
+use lora_rust::store::{Rp2040Flash, Store};
 
-use crate::input::Button2;
+use lora_rust::input::Button2;
 
 struct Disp<D, S>
 where
@@ -169,135 +174,93 @@ fn main() -> ! {
         buffer[i] = c as u8;
     }
     let mut button = Button2::new(pins.gpio15.into_pull_up_input());
+    let timer = bsp::hal::Timer::new(pac.TIMER, &mut pac.RESETS);
+    let mut adc = Adc::new(pac.ADC, &mut pac.RESETS);
+    let mut vsys = pins.voltage_monitor.into_floating_input();
     let mut state = State::Init;
+    let mut outbox: Outbox<1> = Outbox::new();
+    let mut node = Node::new(NODE_ADDRESS);
+    node.key = CHANNEL_KEY;
+    if !NODE_NAME.is_empty() {
+        node.set_name(NODE_NAME);
+    }
+    let mut store = Store::mount(Rp2040Flash::settings());
     let mut disp = Disp {
         display,
         cursor,
         style,
     };
+    settings::renew_epoch(&mut store, &mut node, &mut disp);
 
     loop {
-        state = match state.run_state(&mut lora, &mut button, &mut disp) {
-            Err(stuff::Error::Radio(e)) => match e {
-                sx127xError::Hal(_) => crate::panic!("HAL problem"),
-                sx127xError::InvalidConfiguration => crate::panic!("invalid Configuration"),
-                sx127xError::Aborted => {
-                    info!("Transaction aborted");
-                    State::PrepareIdle
-                }
-                sx127xError::InvalidResponse => {
-                    info!("Invalid response");
-                    State::Reset
-                }
-                sx127xError::Timeout => {
-                    info!("Timeout");
-                    State::Reset
-                }
-                sx127xError::Crc => State::PrepareIdle,
-                sx127xError::BufferSize => State::PrepareIdle,
-                sx127xError::InvalidDevice(_) => {
-                    info!("invalid device, restarting");
-                    State::Reset
-                }
-            },
-            Ok(state) => state,
+        if button.just_pressed() && outbox.is_empty() {
+            _ = outbox.push_back(Outgoing::broadcast(Packet::from_slice(b"Kikooo").unwrap()));
+        }
+        let now = (timer.get_counter() / 1_000) as u32;
+        if reached(now, node.beacon_at) {
+            // measured for the beacon about to go out
+            let raw: Option<u16> = adc.read(&mut vsys).ok();
+            node.presence.battery = raw.and_then(|raw| battery_percent(vsys_millivolts(raw)));
         }
+        if node.epoch_spent() {
+            // the next epoch is reserved before any frame is sealed in it
+            settings::renew_epoch(&mut store, &mut node, &mut disp);
+        }
+        state = match state.run_state(&mut lora, &mut node, &mut outbox, &mut disp, now) {
+            Err(e) => state.on_error(e, &mut node, now),
+            Ok(state) => state,
+        };
     }
 }
 
 use core::fmt::Debug;
 
-impl State {
-    fn run_state<Hal: radio_sx127x::base::Hal, P: InputPin, T: Debug + 'static, D, S>(
-        &self,
-        lora: &mut radio_sx127x::Sx127x<Hal>,
-        button: &mut Button2<P>,
-        disp: &mut Disp<D, S>,
-    ) -> Result<Self, stuff::Error<T>>
-    where
-        stuff::Error<T>: From<sx127xError<T>>,
-        P::Error: Debug,
-        D::Error: Debug,
-        stuff::Error<T>: From<radio_sx127x::Error<<Hal as radio_sx127x::base::Hal>::Error>>,
-        //        DI: display_interface::WriteOnlyDataCommand,
-        //        RST: OutputPin,
-        //        MODEL: mipidsi::models::Model,
-        D: DrawTarget<Color = <S as TextRenderer>::Color>,
-        S: embedded_graphics::text::renderer::TextRenderer + Copy,
-    {
-        match self {
-            State::Init => {
-                info!("init");
-                Ok(State::PrepareIdle)
-            }
-            State::Reset => {
-                crate::panic!("reset unimplemented")
-            }
-            State::PrepareIdle => {
-                info!("to idle");
-                lora.start_receive()?;
-
-                Ok(State::Idle)
-            }
-            State::Idle => {
-                if button.just_pressed() {
-                    info!("Send packet");
-                    lora.start_transmit(b"Kikooo")?;
-                    Ok(State::Sending)
-                } else {
-                    match lora.check_receive(false)? {
-                        true => Ok(State::Received), //have a valid packet in the buffer
-                        false => Ok(State::Idle),    //got an invalid packet
-                    }
-                }
-            }
-            State::Sending => match lora.check_transmit()? {
-                true => Ok(State::SendingDone),
-                false => Ok(State::Sending),
-            },
-            State::Received => {
-                let mut buff = [0u8; 256];
-                let (len, info) = lora.get_received(&mut buff)?;
-                info!(
-                    "received packet len = {} info : {} {}",
-                    len, info.rssi, info.snr
-                );
-                let mut str_buff = [0u8; 20];
-                let text = len.numtoa_str(10, &mut str_buff);
-                Text::new(text, Point::new(60, disp.cursor), disp.style)
-                    .draw(&mut disp.display)
-                    .unwrap();
-                let text = info.rssi.numtoa_str(10, &mut str_buff);
-                Text::new(text, Point::new(60 + 6 * 5, disp.cursor), disp.style)
-                    .draw(&mut disp.display)
-                    .unwrap();
-                if let Some(snr) = info.snr {
-                    let text = (snr).numtoa_str(10, &mut str_buff);
-                    Text::new(
-                        text,
-                        Point::new(60 + 6 * 5 + 6 * 5, disp.cursor),
-                        disp.style,
-                    )
-                    .draw(&mut disp.display)
-                    .unwrap();
-                }
-                disp.cursor += 10;
-                Text::new(
-                    unsafe { core::str::from_utf8_unchecked(&buff[..len]) },
-                    Point::new(60, disp.cursor),
-                    disp.style,
-                )
-                .draw(&mut disp.display)
+impl<D, S> Interface for Disp<D, S>
+where
+    D: DrawTarget<Color = <S as TextRenderer>::Color>,
+    D::Error: Debug,
+    S: embedded_graphics::text::renderer::TextRenderer + Copy,
+{
+    fn set_title(&mut self, _title: &[u8]) {}
+    fn set_input(&mut self, _input: &[u8], _cursor: usize) {}
+    fn set_overlay(&mut self, _overlay: Option<&'static str>) {}
+    fn add_own(&mut self, _id: u16, _to: Option<&[u8]>, body: &[u8], _status: Delivery) {
+        self.add_log(Some(b"me"), body, None, None);
+    }
+    fn set_delivery(&mut self, _id: u16, _status: Delivery) {}
+    fn add_log(&mut self, from: Option<&[u8]>, body: &[u8], snr: Option<i16>, rssi: Option<i16>) {
+        let mut str_buff = [0u8; 20];
+        let text = match from {
+            Some(from) => unsafe { core::str::from_utf8_unchecked(from) },
+            None => body.len().numtoa_str(10, &mut str_buff),
+        };
+        Text::new(text, Point::new(60, self.cursor), self.style)
+            .draw(&mut self.display)
+            .unwrap();
+        if let Some(rssi) = rssi {
+            let text = rssi.numtoa_str(10, &mut str_buff);
+            Text::new(text, Point::new(60 + 6 * 5, self.cursor), self.style)
+                .draw(&mut self.display)
                 .unwrap();
-                disp.cursor += 10;
-                info!("got {},{},{}:{}", len, info.rssi, info.snr, buff[..len]);
-
-                Ok(State::PrepareIdle)
-            }
-            State::SendingDone => {
-                lora.start_receive()?;
-                Ok(Self::Idle)
-            }
         }
+        if let Some(snr) = snr {
+            let text = (snr).numtoa_str(10, &mut str_buff);
+            Text::new(
+                text,
+                Point::new(60 + 6 * 5 + 6 * 5, self.cursor),
+                self.style,
+            )
+            .draw(&mut self.display)
+            .unwrap();
+        }
+        self.cursor += 10;
+        Text::new(
+            unsafe { core::str::from_utf8_unchecked(body) },
+            Point::new(60, self.cursor),
+            self.style,
+        )
+        .draw(&mut self.display)
+        .unwrap();
+        self.cursor += 10;
     }
 }
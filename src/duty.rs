@@ -0,0 +1,151 @@
+//! Rolling airtime budget keeping us within regional duty-cycle limits.
+//!
+//! ETSI EN 300 220 allows 10 % duty cycle on 433.05 to 434.79 MHz, that is
+//! 360 s of transmission in any hour. Airtime is accounted in one minute
+//! buckets, the budget of a bucket coming back an hour after it was used.
+#![allow(dead_code)]
+
+/// Duty cycle allowed around [`crate::sx127x::FREQUENCY`], in per mille.
+pub const DUTY_CYCLE_PERMILLE: u32 = 100;
+pub const WINDOW_MS: u32 = 3_600_000;
+const BUCKETS: usize = 60;
+const BUCKET_MS: u32 = WINDOW_MS / BUCKETS as u32;
+
+pub struct DutyCycle {
+    /// Airtime allowed in any window, in milliseconds.
+    budget: u32,
+    used: [u32; BUCKETS],
+    /// Bucket holding the current minute.
+    current: usize,
+    /// Time the current bucket started.
+    started: u32,
+}
+
+impl DutyCycle {
+    pub fn new(permille: u32) -> Self {
+        Self {
+            budget: WINDOW_MS / 1_000 * permille,
+            used: [0; BUCKETS],
+            current: 0,
+            started: 0,
+        }
+    }
+
+    pub fn budget(&self) -> u32 {
+        self.budget
+    }
+
+    /// Share of the budget left, in percent.
+    pub fn remaining_percent(&mut self, now: u32) -> u32 {
+        let budget = self.budget.max(1);
+        self.remaining(now) * 100 / budget
+    }
+
+    /// Clear the buckets that left the window since the last call.
+    fn roll(&mut self, now: u32) {
+        // minutes since the current one started, across the wrap of `now`
+        let elapsed = now.wrapping_sub(self.started) / BUCKET_MS;
+        for _ in 0..elapsed.min(BUCKETS as u32) {
+            self.current = (self.current + 1) % BUCKETS;
+            self.used[self.current] = 0;
+        }
+        self.started = self.started.wrapping_add(elapsed * BUCKET_MS);
+    }
+
+    /// Airtime spent in the last hour, in milliseconds.
+    pub fn used(&mut self, now: u32) -> u32 {
+        self.roll(now);
+        self.used.iter().sum()
+    }
+
+    /// Airtime left to spend right now, in milliseconds.
+    pub fn remaining(&mut self, now: u32) -> u32 {
+        let budget = self.budget;
+        budget.saturating_sub(self.used(now))
+    }
+
+    /// `true` when a frame of `airtime` ms can never be sent.
+    pub fn too_long(&self, airtime: u32) -> bool {
+        airtime > self.budget
+    }
+
+    /// `true` when a frame of `airtime` ms can be sent now.
+    pub fn allows(&mut self, airtime: u32, now: u32) -> bool {
+        self.remaining(now) >= airtime
+    }
+
+    /// Account for a frame that was just sent.
+    pub fn record(&mut self, airtime: u32, now: u32) {
+        self.roll(now);
+        let used = &mut self.used[self.current];
+        *used = used.saturating_add(airtime);
+    }
+}
+
+impl Default for DutyCycle {
+    fn default() -> Self {
+        Self::new(DUTY_CYCLE_PERMILLE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: u32 = BUCKET_MS;
+
+    #[test]
+    fn refuses_past_the_budget() {
+        let mut duty = DutyCycle::default();
+        assert_eq!(duty.budget(), 360_000);
+        duty.record(359_900, 0);
+        assert!(duty.allows(100, 10));
+        assert!(!duty.allows(101, 10));
+        assert_eq!(duty.remaining(10), 100);
+        assert_eq!(duty.remaining_percent(10), 0);
+        duty.record(500, 20);
+        assert_eq!(duty.remaining(20), 0);
+        assert!(!duty.allows(1, 20));
+    }
+
+    #[test]
+    fn too_long_for_any_window() {
+        let duty = DutyCycle::new(10);
+        assert!(!duty.too_long(36_000));
+        assert!(duty.too_long(36_001));
+    }
+
+    #[test]
+    fn window_slides_a_minute_at_a_time() {
+        let mut duty = DutyCycle::default();
+        duty.record(100_000, 0);
+        duty.record(50_000, 30 * MINUTE + 5);
+        assert_eq!(duty.used(WINDOW_MS - 1), 150_000);
+        assert_eq!(duty.used(WINDOW_MS), 50_000);
+        assert_eq!(duty.remaining_percent(WINDOW_MS), 86);
+        assert_eq!(duty.used(WINDOW_MS + 30 * MINUTE - 1), 50_000);
+        assert_eq!(duty.used(WINDOW_MS + 30 * MINUTE), 0);
+    }
+
+    #[test]
+    fn budget_comes_back_an_hour_later() {
+        let mut duty = DutyCycle::default();
+        duty.record(360_000, 5 * MINUTE);
+        assert!(!duty.allows(1, 30 * MINUTE));
+        // a long silence clears everything at once
+        assert!(duty.allows(360_000, 5 * MINUTE + 3 * WINDOW_MS));
+    }
+
+    #[test]
+    fn clock_wrap_keeps_the_airtime() {
+        let mut duty = DutyCycle::default();
+        let before = u32::MAX - 10 * MINUTE;
+        duty.record(200_000, before);
+        let after = before.wrapping_add(20 * MINUTE);
+        assert!(after < before);
+        assert_eq!(duty.used(after), 200_000);
+        duty.record(100_000, after);
+        assert_eq!(duty.used(before.wrapping_add(WINDOW_MS)), 100_000);
+        assert_eq!(duty.used(after.wrapping_add(WINDOW_MS)), 0);
+    }
+}
@@ -1,11 +1,10 @@
 #![no_std]
 #![no_main]
-mod blink;
-mod input;
-mod interface;
 mod stuff;
 
 use embedded_graphics::text::renderer::TextRenderer;
+use embedded_hal_02::adc::OneShot;
+use lora_rust::sx127x::CONFIG_RADIO;
 use stuff::*;
 
 use bsp::{entry, hal::gpio::FunctionSpi};
@@ -18,7 +17,7 @@ use embedded_hal_compat::eh0_2::digital::v2::OutputPin;
 //use embedded_hal_compat::eh1_0::spi::blocking::{Transactional, TransferInplace, Write};
 use embedded_hal_compat::ForwardCompat;
 use fugit::RateExtU32;
-use input::*;
+use lora_rust::input::{self, *};
 use numtoa::NumToA;
 use panic_probe as _;
 use ssd1681::prelude::*;
@@ -40,6 +39,7 @@ use rp_pico as bsp;
 // use sparkfun_pro_micro_rp2040 as bsp;
 
 use bsp::hal::{
+    adc::Adc,
     clocks::{init_clocks_and_plls, Clock},
     pac,
     sio::Sio,
@@ -53,11 +53,18 @@ use bsp::hal::{
 //use hal::{Pin, Spidev};
 
 use radio_sx127x::prelude::*;
-use radio_sx127x::Error as sx127xError; // Error name conflict with hals
 
-use radio::{Receive, Transmit};
+use lora_rust::history::{self, History, Recorder, Scrollback};
+use lora_rust::interface::menu::{MenuKey, MenuState};
+use lora_rust::interface::{Interface, Oled128x128};
+use lora_rust::neighbours::{battery_percent, vsys_millivolts};
+use lora_rust::node::Node;
+use lora_rust::screen::{self, Screen};
+use lora_rust::settings;
+use lora_rust::state::{reached, Outbox, Outgoing, State};
+use lora_rust::store::{Rp2040Flash, Store};
 
-use crate::input::Button2;
+use lora_rust::input::Button2;
 
 struct Disp<D, S>
 where
@@ -210,12 +217,23 @@ fn main() -> ! {
     _ = pull_up.set_high();
 
     let mut keyboard = Keyboard::new(ShiftRegister::new(k_clk, k_data, k_latch));
+    let timer = bsp::hal::Timer::new(pac.TIMER, &mut pac.RESETS);
+    let mut adc = Adc::new(pac.ADC, &mut pac.RESETS);
+    let mut vsys = pins.voltage_monitor.into_floating_input();
     let mut state = State::Init;
-    let mut buffer = InputBuffer::<128>::new();
+    let mut buffer = InputBuffer::<512>::new();
     //let mut str: String<128> = String::new();
 
     let cursor = 6;
-    let mut sending = false;
+    let mut outbox: Outbox<4> = Outbox::new();
+    let mut node = Node::new(NODE_ADDRESS);
+    node.key = CHANNEL_KEY;
+    if !NODE_NAME.is_empty() {
+        node.set_name(NODE_NAME);
+    }
+    let mut store = Store::mount(Rp2040Flash::settings());
+    let signal = settings::load(&mut store, &mut node);
+    let mut history = History::mount(Rp2040Flash::history());
     // TODO :  drawing above line 6 causes garbage
     //Text::new("Otterly radiolifique", Point::new(0, 6), style)
     //    .draw(&mut display)
@@ -227,20 +245,32 @@ fn main() -> ! {
         style,
     };*/
     let mut interface = Oled128x128::new();
+    if let Some(signal) = signal {
+        interface.set_signal(signal);
+    }
+    history::restore(&mut history, &mut interface, 8);
+    // numbers the entries of this boot in the history
+    let boot = settings::renew_epoch(&mut store, &mut node, &mut interface).unwrap_or(u16::MAX);
+    let mut screen = Screen::Chat;
+    let mut scrollback: Option<Scrollback> = None;
+    // menu keys act when pressed, not while held
+    let mut last_key = Keys::none();
+    // the airtime of the message in the input was shown, validate again to send
+    let mut warned = false;
     interface.set_title(b"Rusty Communicator");
 
     /*
     interface.set_input(b"input", 0);
     interface.set_title(b"title");
-    interface.add_log(b"Squee squee", Some(19), Some(-5));
-    interface.add_log(b"PAtapatate", Some(123), Some(555));
-    interface.add_log(b"Voyage Voyage", None, None);
-    interface.add_log(b"Loutre", None, None);
-    interface.add_log(b"Avion", None, None);
-    interface.add_log(b"Renard", None, None);
-    interface.add_log(b"Carotte", None, None);
-    interface.add_log(b"Chips", None, None);
-    interface.add_log(b"Blop", None, None);
+    interface.add_log(None, b"Squee squee", Some(19), Some(-5));
+    interface.add_log(None, b"PAtapatate", Some(123), Some(555));
+    interface.add_log(None, b"Voyage Voyage", None, None);
+    interface.add_log(None, b"Loutre", None, None);
+    interface.add_log(None, b"Avion", None, None);
+    interface.add_log(None, b"Renard", None, None);
+    interface.add_log(None, b"Carotte", None, None);
+    interface.add_log(None, b"Chips", None, None);
+    interface.add_log(None, b"Blop", None, None);
     */
 
     interface.draw(&mut display_bw);
@@ -265,8 +295,27 @@ fn main() -> ! {
         )
         .draw(&mut disp.display)
         .unwrap();*/
-        if !sending {
-            let key = keyboard.get_keys();
+        let key = keyboard.get_keys();
+        let fresh = key != last_key;
+        last_key = key;
+        if interface.menu_open() {
+            let action = Some(key).filter(|_| fresh).and_then(menu_key);
+            if let Some(action) = action {
+                if interface.menu_key(action, &mut node) == MenuState::Saved {
+                    match settings::save(&mut store, &node, interface.signal()) {
+                        Err(e) => {
+                            info!("settings not saved: {}", e);
+                            interface.add_log(None, b"Settings not saved", None, None);
+                        }
+                        Ok(()) if node.epoch().is_none() => {
+                            // try again, a key set now is told why it cannot seal
+                            settings::renew_epoch(&mut store, &mut node, &mut interface);
+                        }
+                        Ok(()) => {}
+                    }
+                }
+            }
+        } else {
             match buffer.process_input(key) {
                 InputState::Running(key) => {
                     let key = key.and(Keys::Modifiers);
@@ -277,6 +326,7 @@ fn main() -> ! {
                     }
                 }
                 InputState::Updated => {
+                    warned = false;
                     interface.set_input(buffer.get_data(), buffer.get_cursor());
                     info!("{}", buffer);
                 }
@@ -284,38 +334,81 @@ fn main() -> ! {
                     info!("Overflow");
                 }
                 InputState::Validated => {
-                    info!("SENDING {}", buffer);
-                    interface.set_input(b"", 0);
-                    sending = true;
+                    let warning =
+                        screen::long_message(&node, buffer.get_data().len()).filter(|_| !warned);
+                    if let Some(warning) = warning {
+                        interface.add_log(None, warning.as_bytes(), None, None);
+                        warned = true;
+                    } else {
+                        match Outgoing::from_input(&node, buffer.get_data()) {
+                            Some(message) if outbox.push_back(message).is_ok() => {
+                                info!("SENDING {}", buffer);
+                                warned = false;
+                                buffer.clear();
+                                interface.set_input(b"", 0);
+                            }
+                            Some(_) => info!("Outbox full"),
+                            None => interface.add_log(None, b"Unknown recipient", None, None),
+                        }
+                    }
+                }
+                InputState::NotForMe(key) => {
+                    if key.contains(Keys::R) {
+                        screen = screen.toggle(Screen::Routes);
+                    } else if key.contains(Keys::N) {
+                        screen = screen.toggle(Screen::Neighbours);
+                    } else if key.contains(Keys::M) && fresh {
+                        interface.open_menu(&node);
+                    } else if key.contains(Keys::P) && fresh {
+                        // page through the log, then on into the flash history
+                        match &mut scrollback {
+                            Some(open) => open.older(&mut history),
+                            None if interface.page_up() => {}
+                            None => scrollback = Scrollback::open(&mut history),
+                        }
+                    } else if key.contains(Keys::L) && fresh {
+                        match &mut scrollback {
+                            Some(open) => {
+                                if !open.newer(&mut history) {
+                                    scrollback = None;
+                                }
+                            }
+                            None => {
+                                interface.page_down();
+                            }
+                        }
+                    } else if key.contains(Keys::K) {
+                        scrollback = None;
+                        interface.latest();
+                    }
                 }
-                InputState::NotForMe(_key) => {}
             }
         }
-        state = match state.run_state(&mut lora, &mut sending, &mut buffer, &mut interface) {
-            Err(stuff::Error::Radio(e)) => match e {
-                sx127xError::Hal(_) => crate::panic!("HAL problem"),
-                sx127xError::InvalidConfiguration => crate::panic!("invalid Configuration"),
-                sx127xError::Aborted => {
-                    info!("Transaction aborted");
-                    State::PrepareIdle
-                }
-                sx127xError::InvalidResponse => {
-                    info!("Invalid response");
-                    State::Reset
-                }
-                sx127xError::Timeout => {
-                    info!("Timeout");
-                    State::Reset
-                }
-                sx127xError::Crc => State::PrepareIdle,
-                sx127xError::BufferSize => State::PrepareIdle,
-                sx127xError::InvalidDevice(_) => {
-                    info!("invalid device, restarting");
-                    State::Reset
-                }
-            },
+        let now = (timer.get_counter() / 1_000) as u32;
+        if node.epoch_spent() {
+            // the next epoch is reserved before any frame is sealed in it
+            settings::renew_epoch(&mut store, &mut node, &mut interface);
+        }
+        let mut recorder = Recorder {
+            inner: &mut interface,
+            history: &mut history,
+            boot,
+            now,
+        };
+        if reached(now, node.beacon_at) {
+            // measured for the beacon about to go out
+            let raw: Option<u16> = adc.read(&mut vsys).ok();
+            node.presence.battery = raw.and_then(|raw| battery_percent(vsys_millivolts(raw)));
+        }
+        state = match state.run_state(&mut lora, &mut node, &mut outbox, &mut recorder, now) {
+            Err(e) => state.on_error(e, &mut node, now),
             Ok(state) => state,
         };
+        interface.set_page(match &scrollback {
+            Some(scrollback) => Some(scrollback.page.clone()),
+            None => screen.render(&node, now),
+        });
+        interface.set_status("air", &screen::airtime_left(&mut node, now));
         //Pixel(Point::new(127, 127), BinaryColor::On).draw(&mut disp.display);
         interface.draw(&mut display_bw);
         //display.flush().unwrap();
@@ -328,78 +421,21 @@ fn main() -> ! {
     }
 }
 
-use core::fmt::Debug;
-
-use crate::interface::{Interface, Oled128x128};
-
-impl State {
-    fn run_state<Hal: radio_sx127x::base::Hal, T: Debug + 'static /* , D, S*/>(
-        &self,
-        lora: &mut radio_sx127x::Sx127x<Hal>,
-        sending: &mut bool,
-        send_buffer: &mut InputBuffer<128>,
-        disp: &mut impl Interface,
-        //disp: &mut Disp<D, S>,
-    ) -> Result<Self, stuff::Error<T>>
-    where
-        stuff::Error<T>: From<sx127xError<T>>,
-        //D::Error: Debug,
-        stuff::Error<T>: From<radio_sx127x::Error<<Hal as radio_sx127x::base::Hal>::Error>>,
-        //        DI: display_interface::WriteOnlyDataCommand,
-        //        RST: OutputPin,
-        //        MODEL: mipidsi::models::Model,
-        //D: DrawTarget<Color = <S as TextRenderer>::Color>,
-        //S: embedded_graphics::text::renderer::TextRenderer + Copy,
-        //D: DrawTarget<Color = <S as TextRenderer>::Color>,
-        //S: embedded_graphics::text::renderer::TextRenderer + Copy,
-    {
-        match self {
-            State::Init => {
-                info!("init");
-                Ok(State::PrepareIdle)
-            }
-            State::Reset => {
-                crate::panic!("reset unimplemented")
-            }
-            State::PrepareIdle => {
-                lora.start_receive()?;
-
-                Ok(State::Idle)
-            }
-            State::Idle => {
-                if *sending {
-                    info!("Send packet");
-                    lora.start_transmit(send_buffer.get_data())?;
-                    send_buffer.clear();
-                    *sending = false;
-                    Ok(State::Sending)
-                } else {
-                    match lora.check_receive(false)? {
-                        true => Ok(State::Received), //have a valid packet in the buffer
-                        false => Ok(State::Idle),    //got an invalid packet
-                    }
-                }
-            }
-            State::Sending => match lora.check_transmit()? {
-                true => Ok(State::SendingDone),
-                false => Ok(State::Sending),
-            },
-            State::Received => {
-                let mut buff = [0u8; 256];
-                let (len, info) = lora.get_received(&mut buff)?;
-                info!(
-                    "received packet len = {} info : {} {}{}",
-                    len, info.rssi, info.snr, buff
-                );
-                disp.add_log(&buff[..len], info.snr, Some(info.rssi));
-                //Ok(Self::Idle)
-                //lora.start_transmit(&buff[..len])?;
-                Ok(State::PrepareIdle)
-            }
-            State::SendingDone => {
-                lora.start_receive()?;
-                Ok(Self::Idle)
-            }
+/// Keys of the settings menu, Star chords move around like in the input line.
+fn menu_key(key: Keys) -> Option<MenuKey> {
+    if key.contains(Keys::Star) {
+        match key.xor(Keys::Star) {
+            Keys::W => Some(MenuKey::Up),
+            Keys::S => Some(MenuKey::Down),
+            Keys::Q => Some(MenuKey::Left),
+            Keys::E => Some(MenuKey::Right),
+            Keys::M => Some(MenuKey::Back),
+            Keys::ShiftR => Some(MenuKey::Delete),
+            key => Dead::from_key(key).map(MenuKey::Dead),
         }
+    } else if key == Keys::Sharp {
+        Some(MenuKey::Select)
+    } else {
+        key.get_one_char().map(MenuKey::Char)
     }
 }
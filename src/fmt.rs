@@ -0,0 +1,12 @@
+//! Logging macros forwarding to defmt when the `defmt` feature is enabled,
+//! so the library still builds on hosts without a defmt logger.
+#![allow(unused_macros)]
+
+macro_rules! info {
+    ($s:literal $(, $x:expr)* $(,)?) => {{
+        #[cfg(feature = "defmt")]
+        ::defmt::info!($s $(, $x)*);
+        #[cfg(not(feature = "defmt"))]
+        let _ = ($( & $x ),*);
+    }};
+}
@@ -0,0 +1,191 @@
+//! Splitting of messages too long for one frame.
+//!
+//! Fragments of a message use consecutive sequence numbers, the first one
+//! names the whole message and is the one acknowledged. Each fragment frame
+//! carries [`FLAG_FRAGMENT`](crate::frame::FLAG_FRAGMENT) and starts its
+//! payload with:
+//!
+//! ```text
+//! | index | count | chunk ...
+//! ```
+#![allow(dead_code)]
+
+use heapless::{Deque, Vec};
+
+use crate::crypto;
+use crate::frame::{Address, MAX_PAYLOAD};
+use crate::state::{reached, Packet, MAX_MESSAGE};
+
+pub const FRAGMENT_HEADER: usize = 2;
+/// Longest message sent in a single frame, sealed or not.
+pub const MAX_SINGLE: usize = MAX_PAYLOAD - crypto::OVERHEAD;
+/// Part of a message carried by each fragment.
+pub const MAX_CHUNK: usize = MAX_SINGLE - FRAGMENT_HEADER;
+/// Messages being reassembled at the same time.
+pub const MAX_PARTIAL: usize = 2;
+/// Give up on a message when no fragment of it came for this long, long
+/// enough to wait for every retransmission of the sender.
+pub const REASSEMBLY_TIMEOUT_MS: u32 = 15_000;
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FragmentError {
+    TooShort,
+    /// Index or count out of range, or a count disagreeing with earlier
+    /// fragments of the message.
+    BadIndex,
+    /// The chunk does not fit where its index places it.
+    BadLength,
+}
+
+/// Number of frames needed to send `len` bytes.
+pub fn count(len: usize) -> u8 {
+    if len <= MAX_SINGLE {
+        1
+    } else {
+        ((len + MAX_CHUNK - 1) / MAX_CHUNK) as u8
+    }
+}
+
+/// Write fragment `index` of `body` to `out`, returning its length.
+pub fn write(body: &[u8], index: u8, out: &mut [u8; MAX_PAYLOAD]) -> usize {
+    let start = (index as usize * MAX_CHUNK).min(body.len());
+    let chunk = &body[start..body.len().min(start + MAX_CHUNK)];
+    out[0] = index;
+    out[1] = count(body.len());
+    out[FRAGMENT_HEADER..FRAGMENT_HEADER + chunk.len()].copy_from_slice(chunk);
+    FRAGMENT_HEADER + chunk.len()
+}
+
+/// Index and count of a fragment payload.
+pub fn position(payload: &[u8]) -> Result<(u8, u8), FragmentError> {
+    match payload {
+        [index, count, ..] if index < count => Ok((*index, *count)),
+        [_, _, ..] => Err(FragmentError::BadIndex),
+        _ => Err(FragmentError::TooShort),
+    }
+}
+
+struct Partial {
+    src: Address,
+    /// Sequence number of the first fragment.
+    seq: u16,
+    count: u8,
+    /// Bit `n` set once fragment `n` arrived.
+    received: u8,
+    /// Length of the message, known when the last fragment arrived.
+    len: Option<usize>,
+    body: [u8; MAX_MESSAGE],
+    deadline: u32,
+}
+
+/// Messages of which some fragments were received.
+pub struct Reassembly {
+    partial: Vec<Partial, MAX_PARTIAL>,
+    /// Messages completed recently, so retransmissions can be acknowledged.
+    done: Deque<(Address, u16), 4>,
+}
+
+impl Reassembly {
+    pub fn new() -> Self {
+        Self {
+            partial: Vec::new(),
+            done: Deque::new(),
+        }
+    }
+
+    /// Drop messages whose fragments stopped coming.
+    pub fn expire(&mut self, now: u32) {
+        while let Some(i) = self.partial.iter().position(|p| reached(now, p.deadline)) {
+            info!(
+                "reassembly of {} from {} timed out",
+                self.partial[i].seq, self.partial[i].src
+            );
+            self.partial.swap_remove(i);
+        }
+    }
+
+    /// `true` when the message starting at `seq` from `src` was completed.
+    pub fn completed(&self, src: Address, seq: u16) -> bool {
+        self.done.iter().any(|&done| done == (src, seq))
+    }
+
+    /// Store the fragment received as `seq` from `src`, returning the
+    /// message and the sequence number naming it once it is complete.
+    pub fn add(
+        &mut self,
+        src: Address,
+        seq: u16,
+        payload: &[u8],
+        now: u32,
+    ) -> Result<Option<(u16, Packet)>, FragmentError> {
+        let (index, count) = position(payload)?;
+        let chunk = &payload[FRAGMENT_HEADER..];
+        let start = index as usize * MAX_CHUNK;
+        let last = index + 1 == count;
+        if count as usize > u8::BITS as usize
+            || start + chunk.len() > MAX_MESSAGE
+            || (!last && chunk.len() != MAX_CHUNK)
+        {
+            return Err(FragmentError::BadLength);
+        }
+        let first = seq.wrapping_sub(index as u16);
+        let i = match self
+            .partial
+            .iter()
+            .position(|p| p.src == src && p.seq == first)
+        {
+            Some(i) if self.partial[i].count != count => return Err(FragmentError::BadIndex),
+            Some(i) => i,
+            None => {
+                if self.partial.is_full() {
+                    // the message closest to timing out is the least likely to complete
+                    let oldest = self
+                        .partial
+                        .iter()
+                        .enumerate()
+                        .min_by_key(|(_, p)| p.deadline.wrapping_sub(now))
+                        .map(|(i, _)| i)
+                        .unwrap_or(0);
+                    self.partial.swap_remove(oldest);
+                }
+                _ = self.partial.push(Partial {
+                    src,
+                    seq: first,
+                    count,
+                    received: 0,
+                    len: None,
+                    body: [0u8; MAX_MESSAGE],
+                    deadline: 0,
+                });
+                self.partial.len() - 1
+            }
+        };
+        let partial = &mut self.partial[i];
+        partial.body[start..start + chunk.len()].copy_from_slice(chunk);
+        partial.received |= 1 << index;
+        partial.deadline = now.wrapping_add(REASSEMBLY_TIMEOUT_MS);
+        if last {
+            partial.len = Some(start + chunk.len());
+        }
+        let complete = (1u16 << count) - 1;
+        match partial.len {
+            Some(len) if partial.received as u16 == complete => {
+                let message = Packet::from_slice(&partial.body[..len]).unwrap_or_default();
+                self.partial.swap_remove(i);
+                if self.done.is_full() {
+                    self.done.pop_front();
+                }
+                _ = self.done.push_back((src, first));
+                Ok(Some((first, message)))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+impl Default for Reassembly {
+    fn default() -> Self {
+        Self::new()
+    }
+}
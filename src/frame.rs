@@ -0,0 +1,198 @@
+//! Over the air frame format.
+//!
+//! ```text
+//! 0       1         2     4     6     8      9       10    11    13     15    16
+//! | magic | version | src | dst | seq | kind | flags | ttl | via | next | len | payload ...
+//! ```
+//! Multi-byte fields are little endian. `len` must match the payload exactly,
+//! anything else is rejected by [`decode`]. `ttl` is the number of times the
+//! frame may still be relayed, see [`crate::mesh`]. `via` is the node that
+//! sent this copy of the frame and `next` the one that should relay it,
+//! [`BROADCAST`] to let every node do so, see [`crate::routes`].
+#![allow(dead_code)]
+
+use heapless::String;
+
+pub type Address = u16;
+
+pub const MAGIC: u8 = 0xA7;
+pub const VERSION: u8 = 4;
+/// Destination reaching every node.
+pub const BROADCAST: Address = 0xFFFF;
+pub const HEADER_LEN: usize = 16;
+/// Hops given to the frames we originate.
+pub const DEFAULT_TTL: u8 = 3;
+/// Largest payload fitting in one LoRa packet with its header.
+pub const MAX_PAYLOAD: usize = 255 - HEADER_LEN;
+
+/// The sender wants an [`Kind::Ack`] back.
+pub const FLAG_ACK: u8 = 0x01;
+/// The payload is sealed with the channel key, see [`crate::crypto`].
+pub const FLAG_ENCRYPTED: u8 = 0x02;
+/// The payload is one part of a longer message, see [`crate::fragment`].
+pub const FLAG_FRAGMENT: u8 = 0x04;
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[repr(u8)]
+pub enum Kind {
+    /// Text typed by a user.
+    Chat = 0,
+    /// Traffic between nodes, never shown as a message.
+    Control = 1,
+    /// Confirms reception of the frame whose `seq` is the payload.
+    Ack = 2,
+    /// Route table of a neighbour, see [`crate::routes`].
+    Routes = 3,
+    /// Beacon of a neighbour, see [`crate::neighbours`].
+    Presence = 4,
+}
+
+impl TryFrom<u8> for Kind {
+    type Error = FrameError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Kind::Chat),
+            1 => Ok(Kind::Control),
+            2 => Ok(Kind::Ack),
+            3 => Ok(Kind::Routes),
+            4 => Ok(Kind::Presence),
+            other => Err(FrameError::UnknownKind(other)),
+        }
+    }
+}
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FrameError {
+    TooShort,
+    BadMagic,
+    UnsupportedVersion(u8),
+    UnknownKind(u8),
+    /// The length field does not match the received size.
+    LengthMismatch,
+    /// Payload or output buffer too large or too small to encode.
+    TooLong,
+}
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Header {
+    pub src: Address,
+    pub dst: Address,
+    pub seq: u16,
+    pub kind: Kind,
+    pub flags: u8,
+    pub ttl: u8,
+    pub via: Address,
+    pub next: Address,
+}
+
+impl Header {
+    /// Header of a frame we originate, flooded with the default hop limit.
+    pub fn new(src: Address, dst: Address, seq: u16, kind: Kind, flags: u8) -> Self {
+        Self {
+            src,
+            dst,
+            seq,
+            kind,
+            flags,
+            ttl: DEFAULT_TTL,
+            via: src,
+            next: BROADCAST,
+        }
+    }
+
+    /// `true` when a node with `address` should process the frame.
+    pub fn is_for(&self, address: Address) -> bool {
+        self.dst == address || self.dst == BROADCAST
+    }
+}
+
+pub struct Frame<'a> {
+    pub header: Header,
+    pub payload: &'a [u8],
+}
+
+/// Write `header` and `payload` to `out`, returning the frame length.
+pub fn encode(header: &Header, payload: &[u8], out: &mut [u8]) -> Result<usize, FrameError> {
+    let len = HEADER_LEN + payload.len();
+    if payload.len() > MAX_PAYLOAD || out.len() < len {
+        return Err(FrameError::TooLong);
+    }
+    out[0] = MAGIC;
+    out[1] = VERSION;
+    out[2..4].copy_from_slice(&header.src.to_le_bytes());
+    out[4..6].copy_from_slice(&header.dst.to_le_bytes());
+    out[6..8].copy_from_slice(&header.seq.to_le_bytes());
+    out[8] = header.kind as u8;
+    out[9] = header.flags;
+    out[10] = header.ttl;
+    out[11..13].copy_from_slice(&header.via.to_le_bytes());
+    out[13..15].copy_from_slice(&header.next.to_le_bytes());
+    out[15] = payload.len() as u8;
+    out[HEADER_LEN..len].copy_from_slice(payload);
+    Ok(len)
+}
+
+pub fn decode(data: &[u8]) -> Result<Frame<'_>, FrameError> {
+    if data.len() < HEADER_LEN {
+        return Err(FrameError::TooShort);
+    }
+    if data[0] != MAGIC {
+        return Err(FrameError::BadMagic);
+    }
+    if data[1] != VERSION {
+        return Err(FrameError::UnsupportedVersion(data[1]));
+    }
+    let kind = Kind::try_from(data[8])?;
+    let payload = &data[HEADER_LEN..];
+    if payload.len() != data[15] as usize {
+        return Err(FrameError::LengthMismatch);
+    }
+    Ok(Frame {
+        header: Header {
+            src: u16::from_le_bytes([data[2], data[3]]),
+            dst: u16::from_le_bytes([data[4], data[5]]),
+            seq: u16::from_le_bytes([data[6], data[7]]),
+            kind,
+            flags: data[9],
+            ttl: data[10],
+            via: u16::from_le_bytes([data[11], data[12]]),
+            next: u16::from_le_bytes([data[13], data[14]]),
+        },
+        payload,
+    })
+}
+
+/// Name shown for a node we know nothing else about, its address in hex.
+pub fn address_name(address: Address) -> String<4> {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+    let mut name = String::new();
+    for shift in [12, 8, 4, 0] {
+        _ = name.push(DIGITS[(address >> shift) as usize & 0xf] as char);
+    }
+    name
+}
+
+/// Parse a hexadecimal address, usable in constants.
+pub const fn parse_address(s: &str) -> Option<Address> {
+    let bytes = s.as_bytes();
+    if bytes.is_empty() || bytes.len() > 4 {
+        return None;
+    }
+    let mut address: Address = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        let digit = match bytes[i] {
+            b'0'..=b'9' => bytes[i] - b'0',
+            b'a'..=b'f' => bytes[i] - b'a' + 10,
+            b'A'..=b'F' => bytes[i] - b'A' + 10,
+            _ => return None,
+        };
+        address = (address << 4) | digit as Address;
+        i += 1;
+    }
+    Some(address)
+}
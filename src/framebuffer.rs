@@ -0,0 +1,110 @@
+//! Headless 128x128 monochrome draw target.
+//!
+//! Pixels are stored row-major, 8 per byte with the leftmost in the high bit,
+//! which is exactly the raster of a binary PBM (`P4`) image. A screen rendered
+//! by `Oled128x128::draw` can therefore be dumped and compared byte for byte.
+#![allow(dead_code)]
+
+use core::convert::Infallible;
+
+use embedded_graphics::{pixelcolor::BinaryColor, prelude::*};
+
+pub const WIDTH: usize = 128;
+pub const HEIGHT: usize = 128;
+const STRIDE: usize = WIDTH / 8;
+
+/// Header of the PBM image written by [`Framebuffer::write_pbm`].
+pub const PBM_HEADER: &[u8] = b"P4\n128 128\n";
+
+#[derive(Clone, PartialEq)]
+pub struct Framebuffer {
+    raster: [u8; STRIDE * HEIGHT],
+}
+
+impl Framebuffer {
+    pub fn new() -> Self {
+        Self {
+            raster: [0u8; STRIDE * HEIGHT],
+        }
+    }
+
+    /// `true` when the pixel is lit, out of bounds pixels are dark.
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        if x >= WIDTH || y >= HEIGHT {
+            return false;
+        }
+        self.raster[y * STRIDE + x / 8] & (0x80 >> (x % 8)) != 0
+    }
+
+    fn set(&mut self, x: usize, y: usize, on: bool) {
+        let mask = 0x80 >> (x % 8);
+        let byte = &mut self.raster[y * STRIDE + x / 8];
+        if on {
+            *byte |= mask;
+        } else {
+            *byte &= !mask;
+        }
+    }
+
+    /// Raw PBM raster, lit pixels are the PBM's black.
+    pub fn raster(&self) -> &[u8] {
+        &self.raster
+    }
+
+    /// Parse an image written by [`Framebuffer::write_pbm`].
+    pub fn from_pbm(data: &[u8]) -> Option<Self> {
+        let raster = data.strip_prefix(PBM_HEADER)?;
+        let mut fb = Self::new();
+        if raster.len() != fb.raster.len() {
+            return None;
+        }
+        fb.raster.copy_from_slice(raster);
+        Some(fb)
+    }
+
+    /// Number of pixels that differ from `other`.
+    pub fn diff(&self, other: &Self) -> u32 {
+        self.raster
+            .iter()
+            .zip(other.raster.iter())
+            .map(|(a, b)| (a ^ b).count_ones())
+            .sum()
+    }
+
+    #[cfg(feature = "sim")]
+    pub fn write_pbm(&self, w: &mut impl std::io::Write) -> std::io::Result<()> {
+        w.write_all(PBM_HEADER)?;
+        w.write_all(&self.raster)
+    }
+}
+
+impl Default for Framebuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OriginDimensions for Framebuffer {
+    fn size(&self) -> Size {
+        Size::new(WIDTH as u32, HEIGHT as u32)
+    }
+}
+
+impl DrawTarget for Framebuffer {
+    type Color = BinaryColor;
+    type Error = Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, color) in pixels {
+            if let (Ok(x), Ok(y)) = (usize::try_from(point.x), usize::try_from(point.y)) {
+                if x < WIDTH && y < HEIGHT {
+                    self.set(x, y, color.is_on());
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,603 @@
+//! Messages kept in flash across reboots.
+//!
+//! Entries are appended to a ring of sectors, the oldest sector being
+//! erased when the newest is full. Each sector starts with the number it
+//! got in the ring, so the newest one is found again at boot.
+//!
+//! ```text
+//! sector: | magic (2) | version (1) | 0xFF | number (4) | entry ...
+//! entry:  | len (2) | crc (2) | boot (2) | time (4) | rssi (2) | snr | flags |
+//!         | from len | from ... | body ...
+//! ```
+//! `len` counts the bytes after the CRC. Time is in milliseconds since the
+//! boot numbered `boot`, the device having no clock.
+#![allow(dead_code)]
+
+use core::fmt::Write;
+
+use heapless::{Deque, String, Vec};
+
+use crate::interface::{Delivery, Interface, Line, Page};
+use crate::neighbours::NAME_LEN;
+use crate::state::{Packet, MAX_MESSAGE};
+use crate::store::{crc16, Flash, CRC_INIT};
+
+pub const VERSION: u8 = 1;
+const MAGIC: [u8; 2] = *b"HL";
+const SECTOR_HEADER: usize = 8;
+const ENTRY_HEADER: usize = 4;
+const FIXED: usize = 11;
+/// Length of erased flash, marks the end of the entries.
+const FREE: u16 = 0xFFFF;
+const NO_SNR: i8 = i8::MIN;
+const FLAG_OWN: u8 = 0x01;
+const FLAG_RSSI: u8 = 0x02;
+/// Entries in a 4 KiB sector, as few as they can be long.
+const MAX_PER_SECTOR: usize = 4096 / (ENTRY_HEADER + FIXED);
+const MAX_ENTRY: usize = ENTRY_HEADER + FIXED + NAME_LEN + MAX_MESSAGE;
+
+#[derive(Clone, PartialEq, Default)]
+pub struct Entry {
+    /// Boot the entry was written in, numbered by the epoch it reserved,
+    /// `u16::MAX` when it had none. See [`crate::settings::reserve_epoch`].
+    pub boot: u16,
+    /// Milliseconds since that boot.
+    pub time: u32,
+    /// Sender, or recipient of our own message sent to a single node.
+    pub from: String<NAME_LEN>,
+    /// Sent by us.
+    pub own: bool,
+    pub rssi: Option<i16>,
+    pub snr: Option<i16>,
+    pub body: Packet,
+}
+
+impl Entry {
+    /// Who wrote the message, as shown in front of it.
+    pub fn author(&self) -> String<{ NAME_LEN + 3 }> {
+        let mut author = String::new();
+        match (self.own, self.from.is_empty()) {
+            (true, true) => _ = author.push_str("me"),
+            (true, false) => _ = write!(author, "me>{}", self.from),
+            (false, _) => _ = author.push_str(&self.from),
+        }
+        author
+    }
+
+    fn encode(&self, out: &mut Vec<u8, MAX_ENTRY>) {
+        let flags = if self.own { FLAG_OWN } else { 0 } | self.rssi.map_or(0, |_| FLAG_RSSI);
+        let snr = self.snr.map_or(NO_SNR, |snr| snr.clamp(-127, 127) as i8);
+        let len = (FIXED + self.from.len() + self.body.len()) as u16;
+        out.clear();
+        _ = out.extend_from_slice(&len.to_le_bytes());
+        _ = out.extend_from_slice(&[0, 0]);
+        _ = out.extend_from_slice(&self.boot.to_le_bytes());
+        _ = out.extend_from_slice(&self.time.to_le_bytes());
+        _ = out.extend_from_slice(&self.rssi.unwrap_or(0).to_le_bytes());
+        _ = out.extend_from_slice(&[snr as u8, flags, self.from.len() as u8]);
+        _ = out.extend_from_slice(self.from.as_bytes());
+        _ = out.extend_from_slice(&self.body);
+        let crc = crc16(CRC_INIT, &out[ENTRY_HEADER..]);
+        out[2..4].copy_from_slice(&crc.to_le_bytes());
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < FIXED {
+            return None;
+        }
+        let flags = bytes[9];
+        let from_len = bytes[10] as usize;
+        let from = bytes.get(FIXED..FIXED + from_len)?;
+        let snr = bytes[8] as i8;
+        let mut entry = Entry {
+            boot: u16::from_le_bytes([bytes[0], bytes[1]]),
+            time: u32::from_le_bytes([bytes[2], bytes[3], bytes[4], bytes[5]]),
+            own: flags & FLAG_OWN != 0,
+            rssi: Some(i16::from_le_bytes([bytes[6], bytes[7]])).filter(|_| flags & FLAG_RSSI != 0),
+            snr: Some(snr as i16).filter(|_| snr != NO_SNR),
+            body: Packet::from_slice(&bytes[FIXED + from_len..]).ok()?,
+            ..Default::default()
+        };
+        entry.from.push_str(core::str::from_utf8(from).ok()?).ok()?;
+        Some(entry)
+    }
+}
+
+/// Where a walk from the newest entry to the oldest is.
+struct Cursor {
+    /// Sectors before the active one.
+    sector: usize,
+    /// Intact entries of the sector, oldest first.
+    offsets: Vec<u16, MAX_PER_SECTOR>,
+    /// Entries of `offsets` not walked yet.
+    left: usize,
+}
+
+pub struct History<F: Flash> {
+    flash: F,
+    /// Offset of the sector written to.
+    active: usize,
+    number: u32,
+    /// Where the next entry goes.
+    head: usize,
+}
+
+impl<F: Flash> History<F> {
+    /// Open the history in `flash`, starting it empty when no sector is valid.
+    pub fn mount(flash: F) -> Self {
+        let mut history = Self {
+            flash,
+            active: 0,
+            number: 0,
+            head: 0,
+        };
+        let mut found = false;
+        for sector in (0..history.sectors()).map(|i| i * F::SECTOR_SIZE) {
+            match history.header(sector) {
+                Some(number) if !found || number > history.number => {
+                    history.active = sector;
+                    history.number = number;
+                    found = true;
+                }
+                _ => {}
+            }
+        }
+        if !found {
+            history.start(0, 1);
+        }
+        history.head = history.active + SECTOR_HEADER;
+        while let Some((next, _)) = history.entry_at(history.active, history.head) {
+            history.head = next;
+        }
+        let mut len = [0xFF; 2];
+        if history.head + len.len() <= history.active + F::SECTOR_SIZE {
+            history.flash.read(history.head, &mut len);
+        }
+        if u16::from_le_bytes(len) != FREE {
+            // an entry torn by a power loss, it cannot be written over
+            history.advance();
+        }
+        history
+    }
+
+    /// Go on in the next sector of the ring, erasing its entries.
+    fn advance(&mut self) {
+        let next = (self.active + F::SECTOR_SIZE) % (self.sectors() * F::SECTOR_SIZE);
+        self.start(next, self.number.wrapping_add(1));
+    }
+
+    fn sectors(&self) -> usize {
+        self.flash.capacity() / F::SECTOR_SIZE
+    }
+
+    /// Ring number of the sector at `offset`, `None` when it is not ours.
+    fn header(&mut self, offset: usize) -> Option<u32> {
+        let mut header = [0u8; SECTOR_HEADER];
+        self.flash.read(offset, &mut header);
+        if header[0..2] != MAGIC || header[2] != VERSION {
+            return None;
+        }
+        Some(u32::from_le_bytes([
+            header[4], header[5], header[6], header[7],
+        ]))
+    }
+
+    /// Erase the sector at `offset` and make it the newest.
+    fn start(&mut self, offset: usize, number: u32) {
+        self.flash.erase(offset);
+        let mut header = [0xFF; SECTOR_HEADER];
+        header[0..2].copy_from_slice(&MAGIC);
+        header[2] = VERSION;
+        header[4..8].copy_from_slice(&number.to_le_bytes());
+        self.flash.write(offset, &header);
+        self.active = offset;
+        self.number = number;
+        self.head = offset + SECTOR_HEADER;
+    }
+
+    /// Offset of the entry after the one at `offset` of `sector`, and
+    /// whether that one is intact.
+    fn entry_at(&mut self, sector: usize, offset: usize) -> Option<(usize, bool)> {
+        let end = sector + F::SECTOR_SIZE;
+        if offset + ENTRY_HEADER > end {
+            return None;
+        }
+        let mut header = [0u8; ENTRY_HEADER];
+        self.flash.read(offset, &mut header);
+        let len = u16::from_le_bytes([header[0], header[1]]);
+        let next = offset + ENTRY_HEADER + len as usize;
+        if len == FREE || next > end {
+            return None;
+        }
+        let mut crc = CRC_INIT;
+        let mut chunk = [0u8; 64];
+        let mut at = offset + ENTRY_HEADER;
+        while at < next {
+            let n = (next - at).min(chunk.len());
+            self.flash.read(at, &mut chunk[..n]);
+            crc = crc16(crc, &chunk[..n]);
+            at += n;
+        }
+        Some((next, crc == u16::from_le_bytes([header[2], header[3]])))
+    }
+
+    /// Offsets of the intact entries of `sector`, oldest first.
+    fn offsets(&mut self, sector: usize) -> Vec<u16, MAX_PER_SECTOR> {
+        let mut offsets = Vec::new();
+        if self.header(sector).is_none() {
+            return offsets;
+        }
+        let mut offset = sector + SECTOR_HEADER;
+        while let Some((next, intact)) = self.entry_at(sector, offset) {
+            if intact {
+                _ = offsets.push((offset - sector) as u16);
+            }
+            offset = next;
+        }
+        offsets
+    }
+
+    pub fn append(&mut self, entry: &Entry) {
+        let mut record = Vec::new();
+        entry.encode(&mut record);
+        if self.head + record.len() > self.active + F::SECTOR_SIZE {
+            self.advance();
+        }
+        self.flash.write(self.head, &record);
+        self.head += record.len();
+    }
+
+    /// Sector `i` sectors before the active one.
+    fn sector_back(&self, i: usize) -> usize {
+        let ring = self.sectors() * F::SECTOR_SIZE;
+        (self.active + ring - i * F::SECTOR_SIZE) % ring
+    }
+
+    /// Move `cursor` to the sector before its own, `false` past the oldest.
+    fn load_older(&mut self, cursor: &mut Cursor) -> bool {
+        if cursor.sector + 1 >= self.sectors() {
+            return false;
+        }
+        cursor.sector += 1;
+        cursor.offsets = self.offsets(self.sector_back(cursor.sector));
+        cursor.left = cursor.offsets.len();
+        true
+    }
+
+    /// A cursor on the entry `back` entries before the newest one.
+    fn seek(&mut self, back: usize) -> Cursor {
+        let offsets = self.offsets(self.active);
+        let mut cursor = Cursor {
+            sector: 0,
+            left: offsets.len(),
+            offsets,
+        };
+        let mut back = back;
+        while back > 0 {
+            if cursor.left == 0 && !self.load_older(&mut cursor) {
+                break;
+            }
+            let skip = back.min(cursor.left);
+            cursor.left -= skip;
+            back -= skip;
+        }
+        cursor
+    }
+
+    /// The entry under `cursor`, which then moves on to the one before.
+    fn older(&mut self, cursor: &mut Cursor) -> Option<Entry> {
+        while cursor.left == 0 {
+            if !self.load_older(cursor) {
+                return None;
+            }
+        }
+        cursor.left -= 1;
+        let sector = self.sector_back(cursor.sector);
+        let offset = sector + cursor.offsets[cursor.left] as usize;
+        let (next, _) = self.entry_at(sector, offset)?;
+        let mut bytes = [0u8; MAX_ENTRY - ENTRY_HEADER];
+        let len = next - offset - ENTRY_HEADER;
+        self.flash.read(offset + ENTRY_HEADER, &mut bytes[..len]);
+        Entry::decode(&bytes[..len])
+    }
+
+    /// Entry `back` entries before the newest one.
+    pub fn get(&mut self, back: usize) -> Option<Entry> {
+        let mut cursor = self.seek(back);
+        self.older(&mut cursor)
+    }
+
+    /// The history ending `back` entries before the newest one, wrapped to
+    /// the width of the screen, newest at the bottom, and the number of
+    /// entries it shows.
+    pub fn page(&mut self, back: usize) -> (Page, usize) {
+        let mut lines: Deque<Line, 8> = Deque::new();
+        let mut index = back;
+        // one walk down the ring, every sector is read once
+        let mut cursor = self.seek(back);
+        while !lines.is_full() {
+            let entry = match self.older(&mut cursor) {
+                Some(entry) => entry,
+                None => break,
+            };
+            let mut wrapped: Vec<Line, 8> = Vec::new();
+            let mut header = String::new();
+            let (minutes, seconds) = (entry.time / 60_000, entry.time / 1_000 % 60);
+            _ = write!(header, "#{} {}:{:02} ", entry.boot, minutes, seconds);
+            _ = header.push_str(&entry.author());
+            if let Some(rssi) = entry.rssi {
+                _ = write!(header, " {}", rssi);
+            }
+            _ = wrapped.push(header);
+            let body = core::str::from_utf8(&entry.body).unwrap_or("__UNPARSABLE__");
+            let mut line = String::new();
+            for c in body.chars() {
+                let full = line.chars().count() == 21 || c == '\n';
+                if full && wrapped.push(core::mem::take(&mut line)).is_err() {
+                    break;
+                }
+                if c != '\n' {
+                    _ = line.push(c);
+                }
+            }
+            if !line.is_empty() {
+                _ = wrapped.push(line);
+            }
+            // older entries go on top, keep the end of one that does not fit
+            let mut cut = false;
+            while let Some(line) = wrapped.pop() {
+                if lines.push_front(line).is_err() {
+                    cut = true;
+                    break;
+                }
+            }
+            if cut && index > back {
+                // shown whole on the next page
+                break;
+            }
+            index += 1;
+        }
+        (lines.iter().cloned().collect(), index - back)
+    }
+}
+
+/// Pages of history shown in place of the message log.
+pub struct Scrollback {
+    /// Entries between the newest one and the bottom of the page.
+    back: usize,
+    shown: usize,
+    /// `back` of the newer pages, to come back to them.
+    newer: Vec<usize, 32>,
+    pub page: Page,
+}
+
+impl Scrollback {
+    /// The last page of `history`, `None` when it is empty.
+    pub fn open<F: Flash>(history: &mut History<F>) -> Option<Self> {
+        let (page, shown) = history.page(0);
+        if shown == 0 {
+            return None;
+        }
+        Some(Self {
+            back: 0,
+            shown,
+            newer: Vec::new(),
+            page,
+        })
+    }
+
+    /// Page back, staying on the oldest page.
+    pub fn older<F: Flash>(&mut self, history: &mut History<F>) {
+        let back = self.back + self.shown;
+        let (page, shown) = history.page(back);
+        if shown == 0 || self.newer.push(self.back).is_err() {
+            return;
+        }
+        self.back = back;
+        self.shown = shown;
+        self.page = page;
+    }
+
+    /// Page forward, `false` once past the newest page.
+    pub fn newer<F: Flash>(&mut self, history: &mut History<F>) -> bool {
+        match self.newer.pop() {
+            Some(back) => {
+                let (page, shown) = history.page(back);
+                self.back = back;
+                self.shown = shown;
+                self.page = page;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// [`Interface`] writing the messages shown by `inner` to a [`History`].
+pub struct Recorder<'a, I: Interface, F: Flash> {
+    pub inner: &'a mut I,
+    pub history: &'a mut History<F>,
+    pub boot: u16,
+    pub now: u32,
+}
+
+impl<I: Interface, F: Flash> Recorder<'_, I, F> {
+    fn record(&mut self, from: &[u8], own: bool, body: &[u8], snr: Option<i16>, rssi: Option<i16>) {
+        let mut entry = Entry {
+            boot: self.boot,
+            time: self.now,
+            own,
+            rssi,
+            snr,
+            ..Default::default()
+        };
+        _ = entry
+            .from
+            .push_str(core::str::from_utf8(from).unwrap_or(""));
+        _ = entry.body.extend_from_slice(body);
+        self.history.append(&entry);
+    }
+}
+
+impl<I: Interface, F: Flash> Interface for Recorder<'_, I, F> {
+    fn set_title(&mut self, title: &[u8]) {
+        self.inner.set_title(title)
+    }
+
+    fn set_input(&mut self, input: &[u8], cursor: usize) {
+        self.inner.set_input(input, cursor)
+    }
+
+    fn set_overlay(&mut self, overlay: Option<&'static str>) {
+        self.inner.set_overlay(overlay)
+    }
+
+    /// Notices of the node itself, without `from`, are not recorded.
+    fn add_log(&mut self, from: Option<&[u8]>, body: &[u8], snr: Option<i16>, rssi: Option<i16>) {
+        if let Some(from) = from {
+            self.record(from, false, body, snr, rssi);
+        }
+        self.inner.add_log(from, body, snr, rssi)
+    }
+
+    fn add_own(&mut self, id: u16, to: Option<&[u8]>, body: &[u8], status: Delivery) {
+        self.record(to.unwrap_or(b""), true, body, None, None);
+        self.inner.add_own(id, to, body, status)
+    }
+
+    fn set_delivery(&mut self, id: u16, status: Delivery) {
+        self.inner.set_delivery(id, status)
+    }
+}
+
+/// Show the last messages of `history` in `disp`, after a reboot.
+pub fn restore<F: Flash>(history: &mut History<F>, disp: &mut impl Interface, count: usize) {
+    for back in (0..count).rev() {
+        if let Some(entry) = history.get(back) {
+            let author = entry.author();
+            disp.add_log(Some(author.as_bytes()), &entry.body, entry.snr, entry.rssi);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::RamFlash;
+
+    const SECTORS: usize = 4;
+
+    /// [`RamFlash`] counting the sector headers read.
+    struct Counting {
+        ram: RamFlash<{ SECTORS * 4096 }>,
+        headers: usize,
+    }
+
+    impl Flash for Counting {
+        const SECTOR_SIZE: usize = 4096;
+
+        fn capacity(&self) -> usize {
+            self.ram.capacity()
+        }
+
+        fn read(&mut self, offset: usize, buf: &mut [u8]) {
+            if offset & (Self::SECTOR_SIZE - 1) == 0 && buf.len() == SECTOR_HEADER {
+                self.headers += 1;
+            }
+            self.ram.read(offset, buf)
+        }
+
+        fn erase(&mut self, offset: usize) {
+            self.ram.erase(offset)
+        }
+
+        fn write(&mut self, offset: usize, data: &[u8]) {
+            self.ram.write(offset, data)
+        }
+    }
+
+    fn entry(i: usize) -> Entry {
+        let mut entry = Entry {
+            boot: 3,
+            time: i as u32 * 1_000,
+            ..Default::default()
+        };
+        _ = write!(entry.from, "n{}", i % 7);
+        // one to three lines each
+        let mut body: String<64> = String::new();
+        for _ in 0..1 + i % 3 {
+            _ = write!(body, "message number {:04} ", i);
+        }
+        entry.body.extend_from_slice(body.as_bytes()).unwrap();
+        entry
+    }
+
+    fn filled(count: usize) -> History<Counting> {
+        let mut history = History::mount(Counting {
+            ram: RamFlash::new(),
+            headers: 0,
+        });
+        for i in 0..count {
+            history.append(&entry(i));
+        }
+        history
+    }
+
+    #[test]
+    fn entries_come_back_newest_first() {
+        let mut history = filled(400);
+        // the oldest sector was erased to make room
+        let oldest = (0..400).rev().find(|&back| history.get(back).is_some());
+        let kept = oldest.unwrap() + 1;
+        assert!(kept < 400 && kept > 400 / SECTORS, "{}", kept);
+        for back in 0..kept {
+            assert!(history.get(back) == Some(entry(399 - back)), "{}", back);
+        }
+        assert!(history.get(kept).is_none());
+        // and after a reboot
+        let mut history = History::mount(history.flash);
+        assert!(history.get(0) == Some(entry(399)));
+    }
+
+    #[test]
+    fn pages_go_down_the_whole_history() {
+        let mut history = filled(400);
+        let mut back = 0;
+        let mut pages = 0;
+        loop {
+            let (page, shown) = history.page(back);
+            if shown == 0 {
+                assert!(page.is_empty());
+                break;
+            }
+            // the newest entry of the page ends at its bottom
+            let last = entry(399 - back);
+            let body = core::str::from_utf8(&last.body).unwrap();
+            assert!(body.ends_with(page.last().unwrap().as_str()), "{}", back);
+            back += shown;
+            pages += 1;
+        }
+        assert!(history.get(back).is_none());
+        assert!(history.get(back - 1).is_some());
+        assert!(pages > 1);
+    }
+
+    #[test]
+    fn a_page_reads_each_sector_once() {
+        let mut history = filled(400);
+        let mut back = 0;
+        loop {
+            history.flash.headers = 0;
+            let (_, shown) = history.page(back);
+            assert!(
+                history.flash.headers <= SECTORS,
+                "{}",
+                history.flash.headers
+            );
+            if shown == 0 {
+                break;
+            }
+            back += shown;
+        }
+    }
+}
@@ -3,9 +3,10 @@
 use core::mem::size_of;
 
 use bitmask_enum::bitmask;
-use defmt::intern;
-use defmt::Format;
+#[cfg(feature = "defmt")]
+use defmt::{intern, Format};
 use embedded_hal_02::digital::v2::{InputPin, OutputPin};
+use heapless::{Deque, Vec};
 use paste::paste;
 
 pub struct Button<P>
@@ -226,6 +227,7 @@ pub enum InputState {
     NotForMe(Keys),
 }
 
+#[cfg(feature = "defmt")]
 impl<const T: usize> Format for InputBuffer<T> {
     fn format(&self, _fmt: defmt::Formatter) {
         let t = intern!("{=[u8]:a}");
@@ -243,84 +245,425 @@ impl<const T: usize> Format for InputBuffer<T> {
     }
 }
 
+/// Accent typed before the letter it goes on, with a Star chord.
+#[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(Format))]
+pub enum Dead {
+    /// Star+A
+    Acute,
+    /// Star+Z
+    Grave,
+    /// Star+X
+    Circumflex,
+    /// Star+C
+    Cedilla,
+}
+
+/// The accented letters of French, all in Latin-1.
+const COMPOSE: [(Dead, char, char); 20] = [
+    (Dead::Acute, 'e', 'é'),
+    (Dead::Acute, 'E', 'É'),
+    (Dead::Grave, 'a', 'à'),
+    (Dead::Grave, 'e', 'è'),
+    (Dead::Grave, 'u', 'ù'),
+    (Dead::Grave, 'A', 'À'),
+    (Dead::Grave, 'E', 'È'),
+    (Dead::Grave, 'U', 'Ù'),
+    (Dead::Circumflex, 'a', 'â'),
+    (Dead::Circumflex, 'e', 'ê'),
+    (Dead::Circumflex, 'i', 'î'),
+    (Dead::Circumflex, 'o', 'ô'),
+    (Dead::Circumflex, 'u', 'û'),
+    (Dead::Circumflex, 'A', 'Â'),
+    (Dead::Circumflex, 'E', 'Ê'),
+    (Dead::Circumflex, 'I', 'Î'),
+    (Dead::Circumflex, 'O', 'Ô'),
+    (Dead::Circumflex, 'U', 'Û'),
+    (Dead::Cedilla, 'c', 'ç'),
+    (Dead::Cedilla, 'C', 'Ç'),
+];
+
+impl Dead {
+    /// Accent of the Star chord with `key`.
+    pub fn from_key(key: Keys) -> Option<Self> {
+        match key {
+            Keys::A => Some(Dead::Acute),
+            Keys::Z => Some(Dead::Grave),
+            Keys::X => Some(Dead::Circumflex),
+            Keys::C => Some(Dead::Cedilla),
+            _ => None,
+        }
+    }
+
+    /// `c` with the accent, the accent alone after a space, `None` when
+    /// they do not combine.
+    pub fn compose(self, c: char) -> Option<char> {
+        if c == ' ' {
+            return Some(match self {
+                Dead::Acute => '´',
+                Dead::Grave => '`',
+                Dead::Circumflex => '^',
+                Dead::Cedilla => '¸',
+            });
+        }
+        COMPOSE
+            .iter()
+            .find(|&&(dead, base, _)| dead == self && base == c)
+            .map(|&(_, _, composed)| composed)
+    }
+}
+
+/// Changes kept for undo.
+pub const UNDO_DEPTH: usize = 8;
+
+/// A change of the text, undone by its reverse. The text itself is kept in
+/// the ring shared by all changes.
+#[derive(Clone, Copy)]
+struct Change {
+    at: usize,
+    /// Bytes inserted at `at`, or removed from there.
+    len: usize,
+    inserted: bool,
+    /// Cursor before the change.
+    cursor: usize,
+}
+
+/// What a bound key combination does to the line.
+#[derive(Clone, Copy, PartialEq)]
+enum Edit {
+    Left,
+    Right,
+    WordLeft,
+    WordRight,
+    Home,
+    End,
+    Backspace,
+    Delete,
+    DeleteWord,
+    DeleteToEnd,
+    Clear,
+    Undo,
+    Redo,
+}
+
+/// Line editor over UTF-8 text, the cursor moves by characters.
+///
+/// The editing keys are public so each binary can bind its own keyboard,
+/// words are separated by whitespace.
 pub struct InputBuffer<const S: usize> {
-    pub buffer: [u8; S],
+    /// Holds whole characters only, so it is always valid UTF-8.
+    buffer: Vec<u8, S>,
     last: Keys,
     ready: bool,
+    /// Byte offset, always on a character boundary.
     cursor: usize,
+    /// Accent waiting for its letter.
+    dead: Option<Dead>,
+    /// Typing is undone a word at a time. The first `done` changes are
+    /// undone from the last, the ones after them redone.
+    changes: Deque<Change, UNDO_DEPTH>,
+    done: usize,
+    /// Text of `changes` one after the other, the oldest go when it is full.
+    text: Deque<u8, S>,
+    pub left: Keys,
+    pub right: Keys,
+    pub word_left: Keys,
+    pub word_right: Keys,
+    pub home: Keys,
+    pub end: Keys,
+    pub backspace: Keys,
+    /// Delete the character after the cursor.
+    pub delete: Keys,
+    /// Delete the word before the cursor.
+    pub delete_word: Keys,
+    pub delete_to_end: Keys,
+    pub clear: Keys,
+    pub undo_key: Keys,
+    pub redo_key: Keys,
+    pub validate: Keys,
 }
 
 impl<const S: usize> InputBuffer<S> {
     pub fn new() -> Self {
         Self {
-            buffer: [0u8; S],
+            buffer: Vec::new(),
             last: Keys::none(),
             ready: true,
             cursor: 0,
+            dead: None,
+            changes: Deque::new(),
+            done: 0,
+            text: Deque::new(),
+            left: Keys::Star.or(Keys::Q),
+            right: Keys::Star.or(Keys::E),
+            word_left: Keys::Star.or(Keys::ShiftL).or(Keys::Q),
+            word_right: Keys::Star.or(Keys::ShiftL).or(Keys::E),
+            home: Keys::Star.or(Keys::Dollar).or(Keys::Q),
+            end: Keys::Star.or(Keys::Dollar).or(Keys::E),
+            backspace: Keys::Star.or(Keys::ShiftR),
+            delete: Keys::Star.or(Keys::D),
+            delete_word: Keys::Star.or(Keys::ShiftL).or(Keys::ShiftR),
+            delete_to_end: Keys::Star.or(Keys::Dollar).or(Keys::D),
+            clear: Keys::Star.or(Keys::Dollar).or(Keys::ShiftR),
+            undo_key: Keys::Star.or(Keys::U),
+            redo_key: Keys::Star.or(Keys::ShiftL).or(Keys::U),
+            validate: Keys::Sharp,
         }
     }
+    /// Length in bytes.
     pub fn len(&self) -> usize {
-        let mut len = 0;
-        for c in self.buffer {
-            if c != 0 {
-                len += 1;
-            } else {
-                break;
-            }
-        }
-        len
+        self.buffer.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
     }
     pub fn get_data(&self) -> &[u8] {
-        &self.buffer[0..self.len()]
+        &self.buffer
     }
+    fn text(&self) -> &str {
+        core::str::from_utf8(&self.buffer).unwrap_or("")
+    }
+    /// Cursor position in characters.
     pub fn get_cursor(&self) -> usize {
-        self.cursor
+        self.text()[..self.cursor].chars().count()
+    }
+    /// Accent waiting for the next letter.
+    pub fn dead(&self) -> Option<Dead> {
+        self.dead
     }
+    /// Empty the line and forget its undo history.
     pub fn clear(&mut self) {
-        self.buffer = [0u8; S];
+        self.buffer.clear();
         self.cursor = 0;
+        self.dead = None;
+        self.changes.clear();
+        self.done = 0;
+        self.text.clear();
+    }
+    /// Length in bytes of the character before the cursor.
+    fn before(&self) -> Option<usize> {
+        self.text()[..self.cursor]
+            .chars()
+            .next_back()
+            .map(char::len_utf8)
+    }
+    /// Length in bytes of the character after the cursor.
+    fn after(&self) -> Option<usize> {
+        self.text()[self.cursor..]
+            .chars()
+            .next()
+            .map(char::len_utf8)
+    }
+    fn insert(&mut self, car: char) -> InputState {
+        let mut bytes = [0u8; 4];
+        let bytes = car.encode_utf8(&mut bytes).as_bytes();
+        let at = self.cursor;
+        if !self.put(at, bytes) {
+            return InputState::Overflow;
+        }
+        self.record(at, bytes, true);
+        self.cursor = at + bytes.len();
+        InputState::Updated
+    }
+    /// Insert `bytes` at `at`, `false` when they do not fit.
+    fn put(&mut self, at: usize, bytes: &[u8]) -> bool {
+        let len = self.len();
+        if self.buffer.resize(len + bytes.len(), 0).is_err() {
+            return false;
+        }
+        self.buffer.copy_within(at..len, at + bytes.len());
+        self.buffer[at..at + bytes.len()].copy_from_slice(bytes);
+        true
+    }
+    /// Remove the bytes in `start..end`, the cursor is left to the caller.
+    fn remove(&mut self, start: usize, end: usize) {
+        let len = self.len();
+        self.buffer.copy_within(end..len, start);
+        self.buffer.truncate(len - (end - start));
+    }
+    /// Keep the change of `text` at `at` for undo, typing after the last
+    /// insertion extends it until a new word starts.
+    fn record(&mut self, at: usize, text: &[u8], inserted: bool) {
+        // the changes undone cannot be redone after this one
+        while self.changes.len() > self.done {
+            if let Some(change) = self.changes.pop_back() {
+                for _ in 0..change.len {
+                    self.text.pop_back();
+                }
+            }
+        }
+        let new_word = self.text.back().map_or(false, u8::is_ascii_whitespace)
+            && !text.iter().all(u8::is_ascii_whitespace);
+        let room = S - self.text.len();
+        if let Some(last) = self.changes.back_mut() {
+            if inserted
+                && last.inserted
+                && last.at + last.len == at
+                && !new_word
+                && text.len() <= room
+            {
+                last.len += text.len();
+                for &byte in text {
+                    _ = self.text.push_back(byte);
+                }
+                return;
+            }
+        }
+        while self.changes.is_full() || S - self.text.len() < text.len() {
+            match self.changes.pop_front() {
+                Some(oldest) => {
+                    for _ in 0..oldest.len {
+                        self.text.pop_front();
+                    }
+                    self.done -= 1;
+                }
+                None => return,
+            }
+        }
+        for &byte in text {
+            _ = self.text.push_back(byte);
+        }
+        _ = self.changes.push_back(Change {
+            at,
+            len: text.len(),
+            inserted,
+            cursor: self.cursor,
+        });
+        self.done += 1;
+    }
+    /// Change `index` and its text.
+    fn change(&self, index: usize) -> Option<(Change, Vec<u8, S>)> {
+        let change = *self.changes.iter().nth(index)?;
+        let offset: usize = self.changes.iter().take(index).map(|c| c.len).sum();
+        let text = self
+            .text
+            .iter()
+            .skip(offset)
+            .take(change.len)
+            .copied()
+            .collect();
+        Some((change, text))
+    }
+    fn undo(&mut self) -> InputState {
+        let (change, text) = match self.done.checked_sub(1).and_then(|i| self.change(i)) {
+            Some(found) => found,
+            None => return InputState::Overflow,
+        };
+        if change.inserted {
+            self.remove(change.at, change.at + change.len);
+        } else {
+            // the text was there before, it fits again
+            self.put(change.at, &text);
+        }
+        self.cursor = change.cursor;
+        self.done -= 1;
+        InputState::Updated
+    }
+    fn redo(&mut self) -> InputState {
+        let (change, text) = match self.change(self.done) {
+            Some(found) => found,
+            None => return InputState::Overflow,
+        };
+        if change.inserted {
+            self.put(change.at, &text);
+            self.cursor = change.at + change.len;
+        } else {
+            self.remove(change.at, change.at + change.len);
+            self.cursor = change.at;
+        }
+        self.done += 1;
+        InputState::Updated
+    }
+    /// Start of the word before the cursor, or of the one it is in.
+    fn word_start(&self) -> usize {
+        let before = self.text()[..self.cursor].trim_end();
+        before
+            .char_indices()
+            .rev()
+            .find(|(_, c)| c.is_whitespace())
+            .map_or(0, |(i, c)| i + c.len_utf8())
+    }
+    /// Start of the word after the cursor, or the end of the line.
+    fn next_word(&self) -> usize {
+        let after = &self.text()[self.cursor..];
+        let word = after.find(char::is_whitespace).unwrap_or(after.len());
+        let space = after[word..]
+            .find(|c: char| !c.is_whitespace())
+            .unwrap_or(after.len() - word);
+        self.cursor + word + space
+    }
+    fn binding(&self, key: Keys) -> Option<Edit> {
+        [
+            (self.left, Edit::Left),
+            (self.right, Edit::Right),
+            (self.word_left, Edit::WordLeft),
+            (self.word_right, Edit::WordRight),
+            (self.home, Edit::Home),
+            (self.end, Edit::End),
+            (self.backspace, Edit::Backspace),
+            (self.delete, Edit::Delete),
+            (self.delete_word, Edit::DeleteWord),
+            (self.delete_to_end, Edit::DeleteToEnd),
+            (self.clear, Edit::Clear),
+            (self.undo_key, Edit::Undo),
+            (self.redo_key, Edit::Redo),
+        ]
+        .iter()
+        .find(|(binding, _)| *binding == key)
+        .map(|&(_, edit)| edit)
+    }
+    /// Apply `edit`, `Overflow` when it has nothing to act on.
+    fn edit(&mut self, edit: Edit) -> InputState {
+        let len = self.len();
+        // the range to remove and where the cursor goes
+        let (start, end, cursor) = match edit {
+            Edit::Left => (0, 0, self.cursor - self.before().unwrap_or(0)),
+            Edit::Right => (0, 0, self.cursor + self.after().unwrap_or(0)),
+            Edit::WordLeft => (0, 0, self.word_start()),
+            Edit::WordRight => (0, 0, self.next_word()),
+            Edit::Home => (0, 0, 0),
+            Edit::End => (0, 0, len),
+            Edit::Backspace => {
+                let start = self.cursor - self.before().unwrap_or(0);
+                (start, self.cursor, start)
+            }
+            Edit::Delete => {
+                let end = self.cursor + self.after().unwrap_or(0);
+                (self.cursor, end, self.cursor)
+            }
+            Edit::DeleteWord => {
+                let start = self.word_start();
+                (start, self.cursor, start)
+            }
+            Edit::DeleteToEnd => (self.cursor, len, self.cursor),
+            Edit::Clear => (0, len, 0),
+            Edit::Undo => return self.undo(),
+            Edit::Redo => return self.redo(),
+        };
+        if start == end && cursor == self.cursor {
+            return InputState::Overflow;
+        }
+        if start < end {
+            let removed: Vec<u8, S> = Vec::from_slice(&self.buffer[start..end]).unwrap();
+            self.record(start, &removed, false);
+            self.remove(start, end);
+        }
+        self.cursor = cursor;
+        InputState::Updated
     }
     pub fn process_input(&mut self, key: Keys) -> InputState {
         let mut ret = InputState::Running(key);
         if key != self.last {
-            if key.contains(Keys::Star) {
+            if let Some(edit) = self.binding(key) {
+                ret = self.edit(edit);
+            } else if key.contains(Keys::Star) {
                 let key = key.xor(Keys::Star);
-                match key {
-                    Keys::ShiftR => {
-                        if self.cursor == 0 {
-                            ret = InputState::Overflow;
-                        } else {
-                            for i in self.cursor..S {
-                                self.buffer[i - 1] = self.buffer[i];
-                            }
-                            self.cursor -= 1;
-                            self.buffer[S - 1] = 0;
-                            ret = InputState::Updated;
-                        }
-                        //_ = str.pop();
-                        //info!("{}", str.as_str());
-                    }
-                    Keys::Q => {
-                        //Left
-                        if self.cursor == 0 {
-                            ret = InputState::Overflow;
-                        } else {
-                            self.cursor -= 1;
-                            ret = InputState::Updated;
-                        }
-                    }
-                    Keys::E => {
-                        //Right
-                        if self.cursor >= S || self.buffer[self.cursor] == 0 {
-                            ret = InputState::Overflow;
-                        } else {
-                            self.cursor += 1;
-                            ret = InputState::Updated;
-                        }
-                    }
-                    val => {
-                        ret = InputState::NotForMe(val);
-                    }
+                if let Some(dead) = Dead::from_key(key) {
+                    self.dead = Some(dead);
+                    // the letter of the chord is not typed on release of Star
+                    self.ready = false;
+                } else {
+                    ret = InputState::NotForMe(key);
                 }
             } else {
                 let car = key.get_one_char();
@@ -329,34 +672,18 @@ impl<const S: usize> InputBuffer<S> {
                     self.ready = true;
                 }
                 if let (Some(car), true) = (car, self.ready) {
-                    if self.cursor >= S || self.buffer[S - 1] != 0 {
-                        ret = InputState::Overflow;
-                    } else {
-                        for i in (self.cursor..S - 1).rev() {
-                            self.buffer[i + 1] = self.buffer[i];
-                        }
-                        self.buffer[self.cursor] = car as u8;
-                        self.cursor += 1;
-                        ret = InputState::Updated;
-                    }
-                    //_ = str.push(car);
-                    //info!("{}", str.as_str());
+                    // a letter the accent does not go on is typed bare
+                    let car = self
+                        .dead
+                        .take()
+                        .and_then(|dead| dead.compose(car))
+                        .unwrap_or(car);
+                    ret = self.insert(car);
                     self.ready = false;
-                } else if self.ready && key == Keys::Sharp {
+                } else if self.ready && key == self.validate {
                     //info!("SENDING {}", str.as_str());
                     ret = InputState::Validated;
                 }
-                /*info!(
-                    "key is {:08X},{}{}{}{}{} {}    {}",
-                    key,
-                    if m.star { '*' } else { ' ' },
-                    if m.shift_l { '^' } else { ' ' },
-                    if m.shift_r { '%' } else { ' ' },
-                    if m.dollar { '$' } else { ' ' },
-                    if m.sharp { '#' } else { ' ' },
-                    default,
-                    modified
-                )*/
             }
         }
         self.last = key;
@@ -538,3 +865,420 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use heapless::String;
+
+    use super::*;
+
+    /// The key combination typing `c`.
+    fn key(c: char) -> Keys {
+        [
+            (&KEYS_ALPHA, Keys::none()),
+            (&KEYS_CAPS, Keys::ShiftL),
+            (&KEYS_NUM, Keys::Dollar),
+        ]
+        .iter()
+        .find_map(|(layer, modifier)| {
+            let at = layer.iter().position(|&k| k == c)?;
+            Some(Keys::from(1 << at).or(*modifier))
+        })
+        .unwrap()
+    }
+
+    /// Press `keys` and let go.
+    fn press<const S: usize>(input: &mut InputBuffer<S>, keys: Keys) -> InputState {
+        let state = input.process_input(keys);
+        input.process_input(Keys::none());
+        state
+    }
+
+    /// Type `c`, an accented letter after its dead key.
+    fn type_char<const S: usize>(input: &mut InputBuffer<S>, c: char) -> InputState {
+        match COMPOSE.iter().find(|&&(_, _, composed)| composed == c) {
+            Some(&(dead, base, _)) => {
+                let chord = match dead {
+                    Dead::Acute => Keys::A,
+                    Dead::Grave => Keys::Z,
+                    Dead::Circumflex => Keys::X,
+                    Dead::Cedilla => Keys::C,
+                };
+                press(input, Keys::Star.or(chord));
+                press(input, key(base))
+            }
+            None => press(input, key(c)),
+        }
+    }
+
+    fn type_text<const S: usize>(input: &mut InputBuffer<S>, text: &str) {
+        for c in text.chars() {
+            type_char(input, c);
+        }
+    }
+
+    /// `text` typed, the cursor after its first `at` characters.
+    fn typed_at<const S: usize>(text: &str, at: usize) -> InputBuffer<S> {
+        let mut input = InputBuffer::new();
+        type_text(&mut input, text);
+        let (home, right) = (input.home, input.right);
+        press(&mut input, home);
+        for _ in 0..at {
+            press(&mut input, right);
+        }
+        input
+    }
+
+    /// `text` with `c` in place of its characters `start..end`.
+    fn spliced(text: &str, start: usize, end: usize, c: Option<char>) -> String<32> {
+        let before = text.chars().take(start);
+        before.chain(c).chain(text.chars().skip(end)).collect()
+    }
+
+    fn text<const S: usize>(input: &InputBuffer<S>) -> &str {
+        core::str::from_utf8(input.get_data()).unwrap()
+    }
+
+    const WORD: &str = "çà et là";
+
+    #[test]
+    fn insert_at_every_position() {
+        let len = WORD.chars().count();
+        for at in 0..=len {
+            let mut input = typed_at::<32>(WORD, at);
+            assert_eq!(input.get_cursor(), at);
+            assert!(matches!(type_char(&mut input, 'ê'), InputState::Updated));
+            assert_eq!(text(&input), spliced(WORD, at, at, Some('ê')));
+            assert_eq!(input.get_cursor(), at + 1);
+        }
+    }
+
+    #[test]
+    fn delete_at_every_position() {
+        let len = WORD.chars().count();
+        for at in 0..=len {
+            let mut input = typed_at::<32>(WORD, at);
+            let backspace = input.backspace;
+            let state = press(&mut input, backspace);
+            if at == 0 {
+                assert!(matches!(state, InputState::Overflow));
+                assert_eq!(text(&input), WORD);
+            } else {
+                assert_eq!(text(&input), spliced(WORD, at - 1, at, None));
+                assert_eq!(input.get_cursor(), at - 1);
+            }
+            let mut input = typed_at::<32>(WORD, at);
+            let delete = input.delete;
+            let state = press(&mut input, delete);
+            if at == len {
+                assert!(matches!(state, InputState::Overflow));
+                assert_eq!(text(&input), WORD);
+            } else {
+                assert_eq!(text(&input), spliced(WORD, at, at + 1, None));
+                assert_eq!(input.get_cursor(), at);
+            }
+        }
+    }
+
+    #[test]
+    fn cursor_steps_over_whole_characters() {
+        let mut input = InputBuffer::<16>::new();
+        type_text(&mut input, "aé");
+        assert_eq!(input.len(), 3);
+        let left = input.left;
+        press(&mut input, left);
+        assert_eq!(input.get_cursor(), 1);
+        assert_eq!(input.cursor, 1);
+        type_char(&mut input, 'ç');
+        assert_eq!(text(&input), "açé");
+        assert_eq!(input.cursor, 3);
+        let backspace = input.backspace;
+        press(&mut input, backspace);
+        press(&mut input, backspace);
+        assert_eq!(text(&input), "é");
+        assert!(matches!(press(&mut input, left), InputState::Overflow));
+    }
+
+    #[test]
+    fn dead_keys_compose_or_step_aside() {
+        let mut input = InputBuffer::<16>::new();
+        press(&mut input, Keys::Star.or(Keys::X));
+        assert!(input.dead() == Some(Dead::Circumflex));
+        assert!(input.is_empty());
+        type_text(&mut input, "o");
+        // no accent goes on a consonant, it is typed bare
+        press(&mut input, Keys::Star.or(Keys::A));
+        type_text(&mut input, "t ");
+        press(&mut input, Keys::Star.or(Keys::Z));
+        type_text(&mut input, " ");
+        assert_eq!(text(&input), "ôt `");
+        assert!(input.dead().is_none());
+    }
+
+    #[test]
+    fn full_buffer_refuses_what_does_not_fit() {
+        let mut input = InputBuffer::<6>::new();
+        type_text(&mut input, "abcde");
+        let left = input.left;
+        press(&mut input, left);
+        // one byte left, too few for a two byte letter
+        assert!(matches!(type_char(&mut input, 'é'), InputState::Overflow));
+        assert_eq!(text(&input), "abcde");
+        assert!(matches!(type_char(&mut input, 'f'), InputState::Updated));
+        assert_eq!(text(&input), "abcdfe");
+        assert!(matches!(type_char(&mut input, 'g'), InputState::Overflow));
+        assert_eq!(input.get_cursor(), 5);
+    }
+
+    /// Random keys against a plain list of characters.
+    #[test]
+    fn random_edits_match_a_model() {
+        const S: usize = 12;
+        let mut input = InputBuffer::<S>::new();
+        let mut model: Vec<char, S> = Vec::new();
+        let mut cursor = 0;
+        let mut seed = 0x2545_f491u32;
+        for _ in 0..5_000 {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            let bytes: usize = model.iter().map(|c| c.len_utf8()).sum();
+            let c = ['a', 'é', ' ', 'ç', 'Z'][(seed >> 8) as usize % 5];
+            let state = match seed % 8 {
+                0 | 1 => {
+                    let state = type_char(&mut input, c);
+                    if bytes + c.len_utf8() <= S {
+                        assert!(matches!(state, InputState::Updated));
+                        model.insert(cursor, c).unwrap();
+                        cursor += 1;
+                    } else {
+                        assert!(matches!(state, InputState::Overflow));
+                    }
+                    state
+                }
+                2 => {
+                    let left = input.left;
+                    cursor = cursor.saturating_sub(1);
+                    press(&mut input, left)
+                }
+                3 => {
+                    let right = input.right;
+                    cursor = (cursor + 1).min(model.len());
+                    press(&mut input, right)
+                }
+                4 => {
+                    let backspace = input.backspace;
+                    if cursor > 0 {
+                        cursor -= 1;
+                        model.remove(cursor);
+                    }
+                    press(&mut input, backspace)
+                }
+                5 => {
+                    let delete = input.delete;
+                    if cursor < model.len() {
+                        model.remove(cursor);
+                    }
+                    press(&mut input, delete)
+                }
+                6 => {
+                    let home = input.home;
+                    cursor = 0;
+                    press(&mut input, home)
+                }
+                _ => {
+                    let end = input.end;
+                    cursor = model.len();
+                    press(&mut input, end)
+                }
+            };
+            assert!(!matches!(state, InputState::NotForMe(_)));
+            let expected: String<{ S * 2 }> = model.iter().collect();
+            assert_eq!(text(&input), expected);
+            assert_eq!(input.get_cursor(), cursor);
+        }
+    }
+
+    /// Cursor positions reached from both ends by words.
+    #[test]
+    fn cursor_moves_by_words() {
+        let line = "  été  au lac ";
+        let mut input = typed_at::<32>(line, 0);
+        let (word_left, word_right) = (input.word_left, input.word_right);
+        let mut stops: Vec<usize, 8> = Vec::new();
+        while let InputState::Updated = press(&mut input, word_right) {
+            stops.push(input.get_cursor()).unwrap();
+        }
+        assert_eq!(stops, [2, 7, 10, 14]);
+        stops.clear();
+        while let InputState::Updated = press(&mut input, word_left) {
+            stops.push(input.get_cursor()).unwrap();
+        }
+        assert_eq!(stops, [10, 7, 2, 0]);
+    }
+
+    #[test]
+    fn home_and_end() {
+        let mut input = typed_at::<32>(WORD, 3);
+        let (home, end) = (input.home, input.end);
+        assert!(matches!(press(&mut input, end), InputState::Updated));
+        assert_eq!(input.get_cursor(), WORD.chars().count());
+        assert!(matches!(press(&mut input, end), InputState::Overflow));
+        assert!(matches!(press(&mut input, home), InputState::Updated));
+        assert_eq!(input.get_cursor(), 0);
+        assert!(matches!(press(&mut input, home), InputState::Overflow));
+    }
+
+    #[test]
+    fn delete_word_takes_the_spaces_before_the_cursor() {
+        let mut input = typed_at::<32>("déjà  vu", 6);
+        let delete_word = input.delete_word;
+        press(&mut input, delete_word);
+        assert_eq!(text(&input), "vu");
+        assert_eq!(input.get_cursor(), 0);
+        assert!(matches!(
+            press(&mut input, delete_word),
+            InputState::Overflow
+        ));
+        // from inside a word only its start goes
+        let mut input = typed_at::<32>("déjà vu", 6);
+        press(&mut input, delete_word);
+        assert_eq!(text(&input), "déjà u");
+        assert_eq!(input.get_cursor(), 5);
+    }
+
+    #[test]
+    fn delete_to_end_and_clear() {
+        let mut input = typed_at::<32>(WORD, 3);
+        let (delete_to_end, clear) = (input.delete_to_end, input.clear);
+        press(&mut input, delete_to_end);
+        assert_eq!(text(&input), "çà ");
+        assert_eq!(input.get_cursor(), 3);
+        assert!(matches!(
+            press(&mut input, delete_to_end),
+            InputState::Overflow
+        ));
+        let left = input.left;
+        press(&mut input, left);
+        press(&mut input, clear);
+        assert!(input.is_empty());
+        assert_eq!(input.get_cursor(), 0);
+        assert!(matches!(press(&mut input, clear), InputState::Overflow));
+    }
+
+    #[test]
+    fn commands_are_undone_whole() {
+        let mut input = typed_at::<32>(WORD, 3);
+        let undo = input.undo_key;
+        for edit in [input.delete_word, input.delete_to_end, input.clear] {
+            let before = input.get_cursor();
+            press(&mut input, edit);
+            press(&mut input, undo);
+            assert_eq!(text(&input), WORD);
+            assert_eq!(input.get_cursor(), before);
+            // moved on for the next one
+            let right = input.right;
+            press(&mut input, right);
+        }
+    }
+
+    #[test]
+    fn undo_keeps_the_last_changes() {
+        let mut input = InputBuffer::<64>::new();
+        // each word after the first space is a change of its own
+        type_text(&mut input, "a b c d e f g h i j");
+        let undo = input.undo_key;
+        for _ in 0..UNDO_DEPTH {
+            assert!(matches!(press(&mut input, undo), InputState::Updated));
+        }
+        assert_eq!(text(&input), "a b ");
+        assert!(matches!(press(&mut input, undo), InputState::Overflow));
+        let redo = input.redo_key;
+        for _ in 0..UNDO_DEPTH {
+            assert!(matches!(press(&mut input, redo), InputState::Updated));
+        }
+        assert_eq!(text(&input), "a b c d e f g h i j");
+        assert!(matches!(press(&mut input, redo), InputState::Overflow));
+    }
+
+    /// Random edits, each one undone and redone, then all of them.
+    #[test]
+    fn random_edits_undo_and_redo() {
+        let mut input = InputBuffer::<24>::new();
+        let (undo, redo) = (input.undo_key, input.redo_key);
+        let keys = [
+            key('a'),
+            key('b'),
+            key(' '),
+            input.left,
+            input.word_left,
+            input.backspace,
+            input.delete,
+            input.delete_word,
+            input.delete_to_end,
+        ];
+        let mut seed = 0x1b87_3593u32;
+        for step in 1..=3_000 {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            let before: String<24> = String::from(text(&input));
+            let pressed = seed as usize % keys.len();
+            press(&mut input, keys[pressed]);
+            let after: String<24> = String::from(text(&input));
+            let cursor = input.get_cursor();
+            if after != before {
+                press(&mut input, undo);
+                // typing is undone with the rest of its word
+                if keys[pressed].get_one_char().is_none() {
+                    assert_eq!(text(&input), before);
+                }
+                press(&mut input, redo);
+                assert_eq!(text(&input), after);
+                assert_eq!(input.get_cursor(), cursor);
+            }
+            if step % 100 == 0 {
+                let mut undone = 0;
+                while let InputState::Updated = press(&mut input, undo) {
+                    undone += 1;
+                }
+                assert!(undone <= UNDO_DEPTH);
+                for _ in 0..undone {
+                    assert!(matches!(press(&mut input, redo), InputState::Updated));
+                }
+                assert_eq!(text(&input), after);
+            }
+        }
+    }
+
+    #[test]
+    fn long_changes_push_out_the_oldest() {
+        let mut input = InputBuffer::<16>::new();
+        type_text(&mut input, "abcdefgh");
+        let clear = input.clear;
+        press(&mut input, clear);
+        // the typing and the clearing fill the ring, the next change
+        // needs the room of the typing
+        type_text(&mut input, "x");
+        let undo = input.undo_key;
+        press(&mut input, undo);
+        press(&mut input, undo);
+        assert_eq!(text(&input), "abcdefgh");
+        assert!(matches!(press(&mut input, undo), InputState::Overflow));
+    }
+
+    #[test]
+    fn new_change_forgets_the_redo() {
+        let mut input = InputBuffer::<32>::new();
+        type_text(&mut input, "one two");
+        let (undo, redo) = (input.undo_key, input.redo_key);
+        press(&mut input, undo);
+        type_text(&mut input, "six");
+        assert_eq!(text(&input), "one six");
+        assert!(matches!(press(&mut input, redo), InputState::Overflow));
+        press(&mut input, undo);
+        press(&mut input, undo);
+        assert!(input.is_empty());
+        assert!(matches!(press(&mut input, undo), InputState::Overflow));
+    }
+}
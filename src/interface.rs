@@ -1,35 +1,76 @@
 #![allow(dead_code)]
 
+pub mod menu;
+
+use core::fmt::Write;
 use core::i32::MAX;
 
-use defmt::info;
 use embedded_graphics::{
-    mono_font::{ascii::FONT_4X6, ascii::FONT_6X12, MonoTextStyle, MonoTextStyleBuilder},
+    mono_font::{iso_8859_1::FONT_4X6, iso_8859_1::FONT_6X12, MonoTextStyle, MonoTextStyleBuilder},
     pixelcolor::BinaryColor,
     prelude::*,
     primitives::{PrimitiveStyle, PrimitiveStyleBuilder, Rectangle},
     text::*,
 };
-use heapless::String;
+use heapless::{String, Vec};
 use numtoa::NumToA;
 
+use crate::node::Node;
+use crate::view::{LogView, Message, LINES};
+
+use self::menu::{Menu, MenuKey, MenuState};
+
+/// A line of the screen, 21 characters of Latin-1 take up to two bytes each
+/// in UTF-8.
+pub type Line = String<42>;
+/// Lines shown in place of the message log, see [`crate::screen`].
+pub type Page = Vec<Line, 8>;
+
 #[derive(Default, Clone, PartialEq)]
 pub struct LogLine {
-    up: String<6>,
-    down: String<6>,
-    body: String<22>,
+    pub(crate) up: String<6>,
+    pub(crate) down: String<6>,
+    pub(crate) body: Line,
+    /// Sequence number of our own message starting on this line.
+    pub(crate) id: Option<u16>,
+}
+
+/// Delivery of a message we sent.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Delivery {
+    /// Sent without asking for an acknowledgement.
+    Sent,
+    Pending,
+    Delivered,
+    Failed,
+}
+
+impl Delivery {
+    fn marker(self) -> &'static str {
+        match self {
+            Delivery::Sent => "",
+            Delivery::Pending => "..",
+            Delivery::Delivered => "ok",
+            Delivery::Failed => "!!",
+        }
+    }
 }
 
 pub trait Interface {
     fn set_title(&mut self, title: &[u8]);
     fn set_input(&mut self, input: &[u8], cursor: usize);
     fn set_overlay(&mut self, overlay: Option<&'static str>);
-    fn add_log(&mut self, body: &[u8], snr: Option<i16>, rssi: Option<i16>);
+    /// Append a message, `from` names its sender when it came over the air.
+    fn add_log(&mut self, from: Option<&[u8]>, body: &[u8], snr: Option<i16>, rssi: Option<i16>);
+    /// Append a message we sent, `id` identifies it for `set_delivery`.
+    /// `to` names its recipient when it was not sent to the whole channel.
+    fn add_own(&mut self, id: u16, to: Option<&[u8]>, body: &[u8], status: Delivery);
+    fn set_delivery(&mut self, id: u16, status: Delivery);
 }
 
-const SMALL_WIDTH: usize = 4;
-const BIG_WIDTH: usize = 6;
-const DISPLAY_WIDTH: usize = 128;
+pub(crate) const SMALL_WIDTH: usize = 4;
+pub(crate) const BIG_WIDTH: usize = 6;
+pub(crate) const DISPLAY_WIDTH: usize = 128;
 const DISPLAY_CHAR_WIDTH: usize = DISPLAY_WIDTH / BIG_WIDTH;
 impl Interface for Oled128x128<'_> {
     fn set_overlay(&mut self, overlay: Option<&'static str>) {
@@ -72,48 +113,66 @@ impl Interface for Oled128x128<'_> {
         self.input_modified = true;
     }
 
-    fn add_log(&mut self, body: &[u8], snr: Option<i16>, rssi: Option<i16>) {
-        let mut line = LogLine::default();
-        let mut push_line = |line: &mut LogLine| {
-            self.body_modified = true;
-            for i in 1..self.body.len() {
-                self.body[i - 1] = self.body[i].clone()
-            }
-            self.body[self.body.len() - 1] = line.clone();
-            *line = LogLine::default();
-        };
-        if let Some(snr) = snr {
+    fn add_log(&mut self, from: Option<&[u8]>, body: &[u8], snr: Option<i16>, rssi: Option<i16>) {
+        let mut up = String::new();
+        let mut down = String::new();
+        if let Some(snr) = snr.filter(|_| self.signal) {
             let mut str_buff = [0u8; 6];
             let text = snr.numtoa_str(10, &mut str_buff);
-            line.up.push_str(&text).unwrap();
+            up.push_str(&text).unwrap();
         }
-        if let Some(rssi) = rssi {
+        if let Some(rssi) = rssi.filter(|_| self.signal) {
             let mut str_buff = [0u8; 6];
             let text = rssi.numtoa_str(10, &mut str_buff);
-            line.down.push_str(&text).unwrap();
+            down.push_str(&text).unwrap();
         }
-        let prefix = line.up.len().max(line.down.len()) * SMALL_WIDTH;
-        let mut available = (DISPLAY_WIDTH - prefix) / BIG_WIDTH;
+        self.push_message(from, body, up, down, None);
+    }
+
+    fn add_own(&mut self, id: u16, to: Option<&[u8]>, body: &[u8], status: Delivery) {
+        let mut up = String::new();
+        up.push_str(status.marker()).unwrap();
+        let mut from: Vec<u8, 16> = Vec::from_slice(b"me").unwrap();
+        if let Some(to) = to {
+            _ = from.push(b'>');
+            _ = from.extend_from_slice(&to[..to.len().min(from.capacity() - from.len())]);
+        }
+        self.push_message(Some(&from), body, up, String::new(), Some(id));
+    }
+
+    fn set_delivery(&mut self, id: u16, status: Delivery) {
+        if self.log.set_up(id, status.marker()) {
+            self.body_modified = true;
+        }
+    }
+}
+
+impl Oled128x128<'_> {
+    fn push_message(
+        &mut self,
+        from: Option<&[u8]>,
+        body: &[u8],
+        up: String<6>,
+        down: String<6>,
+        id: Option<u16>,
+    ) {
+        let mut message = Message {
+            up,
+            down,
+            id,
+            ..Default::default()
+        };
         if let Ok(s) = core::str::from_utf8(body) {
-            s.chars().for_each(|c| {
-                if c == '\r' || c == '\n' {
-                    push_line(&mut line);
-                    available = DISPLAY_WIDTH / BIG_WIDTH;
-                } else {
-                    if available == 0 {
-                        push_line(&mut line);
-                        available = DISPLAY_WIDTH / BIG_WIDTH;
-                    }
-                    line.body.push(c);
-                    available -= 1;
-                }
-            });
+            if let Some(from) = from.and_then(|from| core::str::from_utf8(from).ok()) {
+                message.push_text(from);
+                message.push_text(": ");
+            }
+            message.push_text(s);
         } else {
-            line.body.push_str("__UNPARSABLE__").unwrap();
-        }
-        if line != LogLine::default() {
-            push_line(&mut line);
+            message.text.push_str("__UNPARSABLE__").unwrap();
         }
+        self.log.push(message);
+        self.body_modified = true;
     }
 }
 
@@ -124,11 +183,20 @@ pub struct Oled128x128<'a> {
     clear_style: PrimitiveStyle<BinaryColor>,
     fill_style: PrimitiveStyle<BinaryColor>,
     overlay_style: MonoTextStyle<'a, BinaryColor>,
+    /// Small print over the log while it is scrolled up.
+    indicator_style: MonoTextStyle<'a, BinaryColor>,
     overlay_text_style: TextStyle,
     overlay: Option<&'static str>,
-    title: String<22>,
-    body: [LogLine; 8],
-    input: String<22>,
+    title: Line,
+    /// Two short lines at the right of the title.
+    status: (String<4>, String<4>),
+    log: LogView,
+    page: Option<Page>,
+    /// Settings being edited, shown over the page and the log.
+    menu: Option<Menu>,
+    /// Show SNR and RSSI next to received messages.
+    signal: bool,
+    input: Line,
     cursor: usize,
     delay: u16,
     overlay_modified: bool,
@@ -150,6 +218,11 @@ impl Oled128x128<'_> {
                 .font(&FONT_6X12)
                 .text_color(BinaryColor::On)
                 .build(),
+            indicator_style: MonoTextStyleBuilder::new()
+                .background_color(BinaryColor::Off)
+                .font(&FONT_4X6)
+                .text_color(BinaryColor::On)
+                .build(),
             clear_style: PrimitiveStyleBuilder::new()
                 .fill_color(BinaryColor::Off)
                 .build(),
@@ -157,7 +230,11 @@ impl Oled128x128<'_> {
                 .fill_color(BinaryColor::On)
                 .build(),
             title: String::default(),
-            body: Default::default(),
+            status: Default::default(),
+            log: LogView::new(),
+            page: None,
+            menu: None,
+            signal: true,
             input: String::default(),
             overlay: None,
             cursor: 0,
@@ -168,6 +245,123 @@ impl Oled128x128<'_> {
             overlay_modified: false,
         }
     }
+    /// Show `up` and `down` stacked in small print at the right of the title.
+    pub fn set_status(&mut self, up: &str, down: &str) {
+        if self.status.0 != up || self.status.1 != down {
+            self.status = (String::new(), String::new());
+            _ = self.status.0.push_str(up);
+            _ = self.status.1.push_str(down);
+            self.title_modified = true;
+        }
+    }
+    /// Show `page` instead of the message log, or the log again on `None`.
+    pub fn set_page(&mut self, page: Option<Page>) {
+        if page != self.page {
+            self.page = page;
+            self.body_modified = true;
+        }
+    }
+    /// Edit the settings `node` runs with and the display's.
+    pub fn open_menu(&mut self, node: &Node) {
+        self.menu = Some(Menu::new(node, self.signal));
+        self.body_modified = true;
+    }
+    pub fn menu_open(&self) -> bool {
+        self.menu.is_some()
+    }
+    /// Hand `key` to the open menu. Once saved the display settings are
+    /// applied here, the others by `node`.
+    pub fn menu_key(&mut self, key: MenuKey, node: &mut Node) -> MenuState {
+        let menu = match &mut self.menu {
+            Some(menu) => menu,
+            None => return MenuState::Closed,
+        };
+        let state = menu.handle(key, node);
+        match state {
+            MenuState::Saved => self.signal = menu.signal,
+            MenuState::Closed => self.menu = None,
+            MenuState::Open => {}
+        }
+        self.body_modified = true;
+        state
+    }
+    pub fn signal(&self) -> bool {
+        self.signal
+    }
+    /// Show SNR and RSSI next to the messages received from now on.
+    pub fn set_signal(&mut self, signal: bool) {
+        self.signal = signal;
+    }
+    /// Show the previous lines of the log, `false` when they are all shown.
+    pub fn page_up(&mut self) -> bool {
+        let moved = self.log.page_up();
+        self.body_modified |= moved;
+        moved
+    }
+    /// Show the next lines of the log, `false` when the newest is shown.
+    pub fn page_down(&mut self) -> bool {
+        let moved = self.log.page_down();
+        self.body_modified |= moved;
+        moved
+    }
+    /// Scroll the log back to the newest message.
+    pub fn latest(&mut self) {
+        self.body_modified |= self.log.scrolled();
+        self.log.latest();
+    }
+    /// Mark every area as modified so the next `draw` repaints the whole screen.
+    pub fn invalidate(&mut self) {
+        self.title_modified = true;
+        self.body_modified = true;
+        self.input_modified = true;
+        self.overlay_modified = true;
+    }
+    fn draw_log(&self, display: &mut impl DrawTarget<Color = BinaryColor>) {
+        let lines = self.log.lines();
+        // the newest line stays at the bottom
+        let first = LINES - lines.len();
+        for (i, line) in lines.iter().enumerate() {
+            let y = (first + i) as i32 * 12 + 16;
+            let u = Text::with_text_style(
+                &line.up,
+                Point::new(0, y),
+                self.style_small,
+                self.text_style,
+            )
+            .draw(display);
+            let d = Text::with_text_style(
+                &line.down,
+                Point::new(0, y + 6),
+                self.style_small,
+                self.text_style,
+            )
+            .draw(display);
+            let x = if let (Ok(u), Ok(d)) = (u, d) {
+                i32::max(u.x, d.x)
+            } else {
+                0
+            };
+            Text::with_text_style(&line.body, Point::new(x, y), self.style, self.text_style)
+                .draw(display);
+        }
+        if self.log.scrolled() {
+            let mut more: String<8> = String::new();
+            match self.log.unseen() {
+                0 => {
+                    _ = more.push_str("more");
+                }
+                n => {
+                    _ = write!(more, "{} new", n);
+                }
+            }
+            let right = TextStyleBuilder::new()
+                .baseline(Baseline::Bottom)
+                .alignment(Alignment::Right)
+                .build();
+            Text::with_text_style(&more, Point::new(127, 114), self.indicator_style, right)
+                .draw(display);
+        }
+    }
     pub fn draw(&mut self, display: &mut impl DrawTarget<Color = BinaryColor>) {
         if self.input_modified {
             Rectangle::new(Point::new(0, 116), Size::new(128, 12))
@@ -201,35 +395,29 @@ impl Oled128x128<'_> {
             self.title_modified = false;
             Text::with_text_style(&self.title, Point::new(0, 0), self.style, self.text_style)
                 .draw(display);
+            let right = TextStyleBuilder::new()
+                .baseline(Baseline::Top)
+                .alignment(Alignment::Right)
+                .build();
+            Text::with_text_style(&self.status.0, Point::new(127, 0), self.style_small, right)
+                .draw(display);
+            Text::with_text_style(&self.status.1, Point::new(127, 6), self.style_small, right)
+                .draw(display);
         }
         if self.body_modified {
             Rectangle::new(Point::new(0, 12), Size::new(128, 128 - 12 * 2))
                 .into_styled(self.clear_style)
                 .draw(display);
             self.body_modified = false;
-            for (i, line) in self.body.iter().enumerate() {
-                let y = i as i32 * 12 + 16;
-                let u = Text::with_text_style(
-                    &line.up,
-                    Point::new(0, y),
-                    self.style_small,
-                    self.text_style,
-                )
-                .draw(display);
-                let d = Text::with_text_style(
-                    &line.down,
-                    Point::new(0, y + 6),
-                    self.style_small,
-                    self.text_style,
-                )
-                .draw(display);
-                let x = if let (Ok(u), Ok(d)) = (u, d) {
-                    i32::max(u.x, d.x)
-                } else {
-                    0
-                };
-                Text::with_text_style(&line.body, Point::new(x, y), self.style, self.text_style)
-                    .draw(display);
+            let menu = self.menu.as_ref().map(Menu::render);
+            if let Some(page) = menu.as_ref().or(self.page.as_ref()) {
+                for (i, line) in page.iter().enumerate() {
+                    let y = i as i32 * 12 + 16;
+                    Text::with_text_style(line, Point::new(0, y), self.style, self.text_style)
+                        .draw(display);
+                }
+            } else {
+                self.draw_log(display);
             }
         }
         if self.body_modified || self.overlay_modified {
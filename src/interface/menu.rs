@@ -0,0 +1,450 @@
+//! Settings menu, shown as a page in place of the message log.
+//!
+//! Up and down select an item, left and right step its value through the
+//! choices or the legal range. Text items are typed into once selected with
+//! [`MenuKey::Select`], which also confirms them. Nothing changes until the
+//! last item, "Save", is selected.
+#![allow(dead_code)]
+
+use core::fmt::Write;
+
+use heapless::String;
+
+use super::Page;
+use crate::airtime;
+use crate::crypto::{parse_key, KEY_LEN};
+use crate::input::Dead;
+use crate::neighbours::NAME_LEN;
+use crate::node::Node;
+use crate::screen::line;
+use crate::settings::{
+    RadioSettings, SettingsError, BANDWIDTHS, BAND_MAX_HZ, BAND_MIN_HZ, CODING_RATES,
+    MAX_POWER_DBM, MIN_POWER_DBM, MIN_PREAMBLE, SPREADING_FACTORS,
+};
+
+/// Frequency spinner step.
+pub const FREQUENCY_STEP_HZ: u32 = 25_000;
+/// Longest preamble offered, longer ones only cost airtime.
+pub const MAX_PREAMBLE: u16 = 64;
+/// Items shown at once, below the status line.
+const VISIBLE: usize = 7;
+const KEY_DIGITS: usize = KEY_LEN * 2;
+
+/// What the keys mean to the menu, each binary maps its keyboard to these.
+#[derive(Clone, Copy, PartialEq)]
+pub enum MenuKey {
+    Up,
+    Down,
+    Left,
+    Right,
+    Select,
+    Back,
+    Delete,
+    /// A typed character, kept only where the item can hold it.
+    Char(char),
+    /// Accent for the next character.
+    Dead(Dead),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Item {
+    Frequency,
+    Bandwidth,
+    SpreadingFactor,
+    CodingRate,
+    Preamble,
+    SyncWord,
+    Power,
+    Name,
+    Key,
+    /// Show SNR and RSSI next to received messages.
+    Signal,
+    Save,
+}
+
+const ITEMS: [Item; 11] = [
+    Item::Frequency,
+    Item::Bandwidth,
+    Item::SpreadingFactor,
+    Item::CodingRate,
+    Item::Preamble,
+    Item::SyncWord,
+    Item::Power,
+    Item::Name,
+    Item::Key,
+    Item::Signal,
+    Item::Save,
+];
+
+impl Item {
+    fn label(self) -> &'static str {
+        match self {
+            Item::Frequency => "Freq",
+            Item::Bandwidth => "Bandwidth",
+            Item::SpreadingFactor => "Spreading",
+            Item::CodingRate => "Coding",
+            Item::Preamble => "Preamble",
+            Item::SyncWord => "Sync word",
+            Item::Power => "Power",
+            Item::Name => "Name",
+            Item::Key => "Key",
+            Item::Signal => "Signal",
+            Item::Save => "Save",
+        }
+    }
+
+    fn is_text(self) -> bool {
+        matches!(self, Item::Name | Item::Key)
+    }
+}
+
+/// Whether the menu is still shown after a key.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MenuState {
+    Open,
+    /// The settings were handed to the node, the display ones are left to
+    /// the caller.
+    Saved,
+    Closed,
+}
+
+/// Settings being edited, applied all at once on "Save".
+pub struct Menu {
+    pub radio: RadioSettings,
+    pub name: String<NAME_LEN>,
+    /// Channel key in hex, empty to send in clear.
+    pub key: String<KEY_DIGITS>,
+    pub signal: bool,
+    selected: usize,
+    /// The selected text item receives the typed characters.
+    typing: bool,
+    /// Accent waiting for its letter.
+    dead: Option<Dead>,
+    status: &'static str,
+}
+
+impl Menu {
+    /// Edit the settings `node` runs with, `signal` being the display's.
+    pub fn new(node: &Node, signal: bool) -> Self {
+        let mut key = String::new();
+        for byte in node.key.iter().flatten() {
+            _ = write!(key, "{:02x}", byte);
+        }
+        Self {
+            radio: *node.radio(),
+            name: node.presence.name.clone(),
+            key,
+            signal,
+            selected: 0,
+            typing: false,
+            dead: None,
+            status: "Settings",
+        }
+    }
+
+    fn item(&self) -> Item {
+        ITEMS[self.selected]
+    }
+
+    pub fn handle(&mut self, key: MenuKey, node: &mut Node) -> MenuState {
+        if self.typing {
+            self.type_key(key);
+            return MenuState::Open;
+        }
+        match key {
+            MenuKey::Up => self.selected = self.selected.saturating_sub(1),
+            MenuKey::Down => self.selected = (self.selected + 1).min(ITEMS.len() - 1),
+            MenuKey::Left => self.step(false),
+            MenuKey::Right => self.step(true),
+            MenuKey::Select if self.item() == Item::Save => match self.apply(node) {
+                Ok(()) => {
+                    self.status = "Saved";
+                    return MenuState::Saved;
+                }
+                Err(e) => self.status = error_text(e),
+            },
+            MenuKey::Select if self.item().is_text() => {
+                self.typing = true;
+                self.status = "Typing, select: done";
+            }
+            MenuKey::Back => return MenuState::Closed,
+            _ => {}
+        }
+        MenuState::Open
+    }
+
+    fn type_key(&mut self, key: MenuKey) {
+        let item = self.item();
+        match key {
+            MenuKey::Select | MenuKey::Back => {
+                self.typing = false;
+                self.dead = None;
+                self.status = "Settings";
+            }
+            MenuKey::Delete if item == Item::Name => {
+                self.name.pop();
+            }
+            MenuKey::Delete => {
+                self.key.pop();
+            }
+            MenuKey::Dead(dead) => self.dead = Some(dead),
+            MenuKey::Char(c) => {
+                // a letter the accent does not go on is typed bare
+                let c = self
+                    .dead
+                    .take()
+                    .and_then(|dead| dead.compose(c))
+                    .unwrap_or(c);
+                match item {
+                    Item::Name if name_char(c) => _ = self.name.push(c),
+                    Item::Key if c.is_ascii_hexdigit() => _ = self.key.push(c),
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Move the value of the selected item to its next or previous choice.
+    fn step(&mut self, up: bool) {
+        let item = self.item();
+        let radio = &mut self.radio;
+        match item {
+            Item::Frequency => {
+                radio.freq = spin(radio.freq, FREQUENCY_STEP_HZ, BAND_MIN_HZ, BAND_MAX_HZ, up)
+            }
+            Item::Bandwidth => radio.bw = pick(&BANDWIDTHS, radio.bw, up),
+            Item::SpreadingFactor => radio.sf = pick(&SPREADING_FACTORS, radio.sf, up),
+            Item::CodingRate => radio.cr = pick(&CODING_RATES, radio.cr, up),
+            Item::Preamble => {
+                radio.preamble_len = spin(radio.preamble_len, 1, MIN_PREAMBLE, MAX_PREAMBLE, up)
+            }
+            Item::SyncWord => radio.sync_word = spin(radio.sync_word, 1, 0, u8::MAX, up),
+            Item::Power => radio.power = spin(radio.power, 1, MIN_POWER_DBM, MAX_POWER_DBM, up),
+            Item::Signal => self.signal = !self.signal,
+            Item::Name | Item::Key | Item::Save => {}
+        }
+    }
+
+    /// Hand the edited settings to `node`, all of them or none.
+    pub fn apply(&self, node: &mut Node) -> Result<(), SettingsError> {
+        let key = match self.key.len() {
+            0 => None,
+            _ => Some(parse_key(&self.key).ok_or(SettingsError::Key)?),
+        };
+        node.set_radio(self.radio)?;
+        node.set_name(&self.name);
+        node.key = key;
+        Ok(())
+    }
+
+    pub fn render(&self) -> Page {
+        let mut page = Page::new();
+        line(&mut page, format_args!("{}", self.status));
+        let first = self
+            .selected
+            .saturating_sub(VISIBLE - 1)
+            .min(ITEMS.len() - VISIBLE);
+        for (i, item) in ITEMS.iter().enumerate().skip(first).take(VISIBLE) {
+            let marker = match (i == self.selected, self.typing) {
+                (true, true) => '*',
+                (true, false) => '>',
+                _ => ' ',
+            };
+            let mut value: String<12> = String::new();
+            self.value(*item, &mut value);
+            line(
+                &mut page,
+                format_args!("{}{:<9}{:>11}", marker, item.label(), value),
+            );
+        }
+        page
+    }
+
+    fn value(&self, item: Item, out: &mut String<12>) {
+        let radio = &self.radio;
+        _ = match item {
+            Item::Frequency => write!(
+                out,
+                "{}.{:03}",
+                radio.freq / 1_000_000,
+                radio.freq % 1_000_000 / 1_000
+            ),
+            Item::Bandwidth => write!(out, "{}k", airtime::bandwidth_hz(radio.bw) / 1_000),
+            Item::SpreadingFactor => write!(out, "SF{}", airtime::spreading_factor(radio.sf)),
+            Item::CodingRate => write!(out, "4/{}", 4 + airtime::coding_rate(radio.cr)),
+            Item::Preamble => write!(out, "{}", radio.preamble_len),
+            Item::SyncWord => write!(out, "0x{:02x}", radio.sync_word),
+            Item::Power => write!(out, "{}dBm", radio.power),
+            Item::Name => write!(out, "{}", tail(&self.name, 11)),
+            // the key is only shown while typing it
+            Item::Key if self.typing && self.item() == Item::Key => {
+                write!(out, "{}", tail(&self.key, 11))
+            }
+            Item::Key if self.key.is_empty() => write!(out, "none"),
+            Item::Key => write!(out, "set"),
+            Item::Signal => write!(out, "{}", if self.signal { "on" } else { "off" }),
+            Item::Save => Ok(()),
+        };
+    }
+}
+
+/// Step `value` by `step` within `min..=max`.
+fn spin<T>(value: T, step: T, min: T, max: T, up: bool) -> T
+where
+    T: Copy + Ord + core::ops::Add<Output = T> + core::ops::Sub<Output = T>,
+{
+    if up {
+        if max - value < step {
+            max
+        } else {
+            value + step
+        }
+    } else if value - min < step {
+        min
+    } else {
+        value - step
+    }
+}
+
+/// Next or previous of `choices` after `value`, wrapping around.
+fn pick<T: Copy + PartialEq>(choices: &[T], value: T, up: bool) -> T {
+    let i = choices.iter().position(|&c| c == value).unwrap_or(0);
+    let i = if up {
+        (i + 1) % choices.len()
+    } else {
+        (i + choices.len() - 1) % choices.len()
+    };
+    choices[i]
+}
+
+/// `true` for the characters a name may hold: those the Latin-1 font draws,
+/// spaces aside so that `@name` still reaches its owner.
+pub fn name_char(c: char) -> bool {
+    matches!(c, '!'..='~' | '\u{a1}'..='\u{ff}')
+}
+
+/// Last `n` characters of `s`.
+fn tail(s: &str, n: usize) -> &str {
+    let skip = s.chars().count().saturating_sub(n);
+    s.char_indices().nth(skip).map_or("", |(i, _)| &s[i..])
+}
+
+fn error_text(e: SettingsError) -> &'static str {
+    match e {
+        SettingsError::Frequency => "Freq out of band",
+        SettingsError::SpreadingFactor => "Bad spreading",
+        SettingsError::Preamble => "Preamble too short",
+        SettingsError::Power => "Power not allowed",
+        SettingsError::Key => "Key: 64 hex digits",
+        SettingsError::Corrupted => "Corrupted settings",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spin_stops_at_the_bounds() {
+        assert_eq!(spin(10u32, 3, 0, 20, true), 13);
+        assert_eq!(spin(19u32, 3, 0, 20, true), 20);
+        assert_eq!(spin(20u32, 3, 0, 20, true), 20);
+        assert_eq!(spin(2u32, 3, 0, 20, false), 0);
+        // no overflow at the ends of the type
+        assert_eq!(spin(254u8, 1, 0, u8::MAX, true), u8::MAX);
+        assert_eq!(spin(u8::MAX, 1, 0, u8::MAX, true), u8::MAX);
+        assert_eq!(spin(-3i8, 1, -4, 4, false), -4);
+        assert_eq!(spin(-4i8, 1, -4, 4, false), -4);
+    }
+
+    #[test]
+    fn pick_wraps_around() {
+        let choices = [1, 2, 4];
+        assert_eq!(pick(&choices, 2, true), 4);
+        assert_eq!(pick(&choices, 4, true), 1);
+        assert_eq!(pick(&choices, 1, false), 4);
+        // a value off the list starts from the first choice
+        assert_eq!(pick(&choices, 3, true), 2);
+    }
+
+    #[test]
+    fn tail_counts_characters() {
+        assert_eq!(tail("abc", 5), "abc");
+        assert_eq!(tail("abcdef", 2), "ef");
+        assert_eq!(tail("déjà", 2), "jà");
+        assert_eq!(tail("déjà", 3), "éjà");
+        assert_eq!(tail("", 3), "");
+    }
+
+    fn select(menu: &mut Menu, item: Item) {
+        menu.selected = ITEMS.iter().position(|&i| i == item).unwrap();
+    }
+
+    fn type_in(menu: &mut Menu, node: &mut Node, item: Item, text: &str) {
+        select(menu, item);
+        menu.handle(MenuKey::Select, node);
+        for c in text.chars() {
+            menu.handle(MenuKey::Char(c), node);
+        }
+        menu.handle(MenuKey::Select, node);
+    }
+
+    #[test]
+    fn names_keep_what_can_be_drawn_and_addressed() {
+        let mut node = Node::new(1);
+        let mut menu = Menu::new(&node, true);
+        menu.name.clear();
+        type_in(&mut menu, &mut node, Item::Name, "Zoë Ω\tö~");
+        assert_eq!(menu.name, "Zoëö~");
+        menu.name.clear();
+        menu.handle(MenuKey::Select, &mut node);
+        for key in [
+            MenuKey::Char('L'),
+            MenuKey::Dead(Dead::Acute),
+            MenuKey::Char('e'),
+            MenuKey::Dead(Dead::Cedilla),
+            MenuKey::Char('o'),
+            MenuKey::Dead(Dead::Circumflex),
+        ] {
+            menu.handle(key, &mut node);
+        }
+        menu.handle(MenuKey::Select, &mut node);
+        assert_eq!(menu.name, "Léo");
+        assert!(menu.dead.is_none());
+        type_in(&mut menu, &mut node, Item::Key, "0xg1F");
+        assert_eq!(menu.key, "01F");
+    }
+
+    #[test]
+    fn saving_applies_all_or_nothing() {
+        let mut node = Node::new(1);
+        let radio = *node.radio();
+        let name = node.presence.name.clone();
+        let mut menu = Menu::new(&node, true);
+        menu.name.clear();
+        type_in(&mut menu, &mut node, Item::Name, "ada");
+        select(&mut menu, Item::Power);
+        menu.handle(MenuKey::Right, &mut node);
+        menu.key.push_str("abc").unwrap();
+        select(&mut menu, Item::Save);
+        assert_eq!(menu.handle(MenuKey::Select, &mut node), MenuState::Open);
+        assert_eq!(menu.status, "Key: 64 hex digits");
+
+        menu.key.clear();
+        menu.radio.freq = BAND_MAX_HZ;
+        assert_eq!(menu.apply(&mut node), Err(SettingsError::Frequency));
+        assert_eq!(*node.radio(), radio);
+        assert_eq!(node.presence.name, name);
+        assert_eq!(node.key, None);
+
+        menu.radio.freq = radio.freq;
+        for _ in 0..KEY_LEN {
+            menu.key.push_str("a5").unwrap();
+        }
+        assert_eq!(menu.handle(MenuKey::Select, &mut node), MenuState::Saved);
+        assert_eq!(node.radio().power, radio.power + 1);
+        assert_eq!(node.presence.name, "ada");
+        assert_eq!(node.key, Some([0xa5; KEY_LEN]));
+    }
+}
@@ -0,0 +1,105 @@
+//! Listen before talk.
+//!
+//! A frame is only sent once the channel is found clear. Each time it is
+//! busy, the next look is put off by a random number of slots drawn from a
+//! window doubling up to [`MAX_EXPONENT`], like Ethernet's backoff.
+#![allow(dead_code)]
+
+use crate::state::reached;
+
+/// Received signal above which the channel is considered busy.
+pub const BUSY_THRESHOLD_DBM: i16 = -90;
+/// Unit of the random backoff, about the airtime of a short frame at SF7.
+pub const SLOT_MS: u32 = 25;
+/// The backoff window stops doubling after this many busy channels.
+pub const MAX_EXPONENT: u8 = 5;
+/// Busy channels after which the frame is sent anyway, so a jammed or
+/// noisy channel cannot stall the node forever.
+pub const MAX_ATTEMPTS: u8 = 8;
+
+#[derive(Default)]
+pub struct Backoff {
+    attempt: u8,
+    until: u32,
+}
+
+impl Backoff {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `true` once the wait chosen by the last [`Backoff::busy`] is over.
+    pub fn ready(&self, now: u32) -> bool {
+        self.attempt == 0 || reached(now, self.until)
+    }
+
+    /// `true` when the channel was busy too many times in a row to keep waiting.
+    pub fn exhausted(&self) -> bool {
+        self.attempt >= MAX_ATTEMPTS
+    }
+
+    /// The channel was busy, wait a random number of slots drawn from
+    /// `random`, returning the time to look again.
+    pub fn busy(&mut self, random: u32, now: u32) -> u32 {
+        let slots = 1u32 << self.attempt.min(MAX_EXPONENT);
+        self.attempt = self.attempt.saturating_add(1);
+        self.until = now.wrapping_add((1 + random % slots) * SLOT_MS);
+        self.until
+    }
+
+    /// The channel was clear and the frame is on its way.
+    pub fn sent(&mut self) {
+        self.attempt = 0;
+    }
+
+    /// Busy channels seen for the frame waiting to be sent.
+    pub fn attempt(&self) -> u8 {
+        self.attempt
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn window_doubles_up_to_the_cap() {
+        let mut backoff = Backoff::new();
+        assert!(backoff.ready(0));
+        for attempt in 0..MAX_ATTEMPTS as u32 {
+            let slots = 1 << attempt.min(MAX_EXPONENT as u32);
+            // the longest and shortest waits of the window
+            let mut longest = Backoff {
+                attempt: attempt as u8,
+                until: 0,
+            };
+            assert_eq!(longest.busy(slots - 1, 1_000), 1_000 + slots * SLOT_MS);
+            let at = backoff.busy(slots, 1_000);
+            assert_eq!(at, 1_000 + SLOT_MS);
+        }
+        assert_eq!(backoff.attempt(), MAX_ATTEMPTS);
+    }
+
+    #[test]
+    fn waits_until_the_chosen_time() {
+        let mut backoff = Backoff::new();
+        let at = backoff.busy(1, u32::MAX - 10);
+        assert_eq!(at, (u32::MAX - 10).wrapping_add(SLOT_MS));
+        assert!(!backoff.ready(u32::MAX));
+        assert!(!backoff.ready(at.wrapping_sub(1)));
+        assert!(backoff.ready(at));
+    }
+
+    #[test]
+    fn gives_up_then_starts_over() {
+        let mut backoff = Backoff::new();
+        for _ in 0..MAX_ATTEMPTS {
+            assert!(!backoff.exhausted());
+            backoff.busy(0, 0);
+        }
+        assert!(backoff.exhausted());
+        backoff.sent();
+        assert!(!backoff.exhausted());
+        assert!(backoff.ready(0));
+    }
+}
@@ -0,0 +1,36 @@
+//! Hardware independent part of the communicator.
+//!
+//! Everything here builds without `rp_pico` so it can be exercised on the host,
+//! the binaries only wire pins to it.
+#![no_std]
+
+#[cfg(feature = "sim")]
+extern crate std;
+
+#[macro_use]
+mod fmt;
+
+pub mod airtime;
+pub mod blink;
+pub mod crypto;
+pub mod duty;
+pub mod fragment;
+pub mod frame;
+pub mod framebuffer;
+pub mod history;
+pub mod input;
+pub mod interface;
+pub mod lbt;
+pub mod mesh;
+pub mod neighbours;
+pub mod node;
+pub mod replay;
+pub mod routes;
+pub mod screen;
+pub mod settings;
+#[cfg(feature = "sim")]
+pub mod sim;
+pub mod state;
+pub mod store;
+pub mod sx127x;
+pub mod view;
@@ -4,7 +4,6 @@
 #![no_std]
 #![no_main]
 
-mod blink;
 mod stuff;
 use bsp::entry;
 use defmt::*;
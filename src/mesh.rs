@@ -0,0 +1,170 @@
+//! Flood relaying of frames between nodes out of range of each other.
+//!
+//! Every frame not addressed to us alone is sent again once, with its `ttl`
+//! decremented, after a random delay so that neighbours hearing the same
+//! frame do not all rebroadcast it at the same time. Frames are recognised
+//! by their source, epoch and sequence number, relays never need the
+//! channel key as the epoch of sealed frames is sent in clear.
+#![allow(dead_code)]
+
+use heapless::{Deque, Vec};
+
+use crate::crypto;
+use crate::frame::{self, Address, Header, FLAG_ENCRYPTED};
+use crate::state::{reached, MAX_PACKET};
+
+/// Frames remembered to avoid relaying them twice.
+pub const SEEN: usize = 32;
+/// Relayed frames waiting for their turn.
+pub const MAX_RELAYS: usize = 4;
+/// Shortest wait before relaying, leaves time to the destination to answer.
+pub const RELAY_DELAY_MS: u32 = 50;
+/// Upper bound of the random wait added to [`RELAY_DELAY_MS`].
+pub const RELAY_JITTER_MS: u32 = 400;
+
+struct Relay {
+    at: u32,
+    frame: Vec<u8, MAX_PACKET>,
+}
+
+pub struct Flood {
+    /// Relay frames of other nodes, can be turned off to save battery.
+    pub enabled: bool,
+    /// Source, epoch and sequence number of the frames heard.
+    seen: Deque<(Address, u16, u16), SEEN>,
+    queue: Deque<Relay, MAX_RELAYS>,
+    /// Frames sent again for other nodes.
+    pub relayed: u32,
+}
+
+impl Default for Flood {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Flood {
+    pub fn new() -> Self {
+        Self {
+            enabled: true,
+            seen: Deque::new(),
+            queue: Deque::new(),
+            relayed: 0,
+        }
+    }
+
+    /// Remember the frame, returning `false` if it was already heard.
+    fn first_time(&mut self, header: &Header, payload: &[u8]) -> bool {
+        // frames in clear carry no epoch, a restarted sender reuses their ids
+        let epoch = match header.flags & FLAG_ENCRYPTED {
+            0 => 0,
+            _ => crypto::epoch(payload).unwrap_or(0),
+        };
+        let id = (header.src, epoch, header.seq);
+        if self.seen.iter().any(|&seen| seen == id) {
+            return false;
+        }
+        if self.seen.is_full() {
+            self.seen.pop_front();
+        }
+        _ = self.seen.push_back(id);
+        true
+    }
+
+    /// Schedule a received frame for relaying at `at` if it still has hops
+    /// left, sent by us `via` to the `next` hop.
+    pub fn offer(&mut self, header: &Header, payload: &[u8], via: Address, next: Address, at: u32) {
+        if !self.first_time(header, payload) || !self.enabled || header.ttl == 0 {
+            return;
+        }
+        let mut relay = Relay {
+            at,
+            frame: Vec::new(),
+        };
+        _ = relay.frame.resize_default(MAX_PACKET);
+        let header = Header {
+            ttl: header.ttl - 1,
+            via,
+            next,
+            ..*header
+        };
+        match frame::encode(&header, payload, &mut relay.frame) {
+            Ok(len) => relay.frame.truncate(len),
+            Err(_) => return,
+        }
+        if self.queue.push_back(relay).is_err() {
+            info!(
+                "relay queue full, dropping {} from {}",
+                header.seq, header.src
+            );
+        }
+    }
+
+    /// Next relayed frame whose delay is over.
+    pub fn due(&mut self, now: u32) -> Option<Vec<u8, MAX_PACKET>> {
+        match self.queue.front() {
+            Some(relay) if reached(now, relay.at) => {
+                self.relayed = self.relayed.wrapping_add(1);
+                self.queue.pop_front().map(|relay| relay.frame)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::{Kind, BROADCAST};
+
+    fn header(seq: u16, flags: u8) -> Header {
+        Header::new(1, BROADCAST, seq, Kind::Chat, flags)
+    }
+
+    #[test]
+    fn relayed_once_with_one_hop_less() {
+        let mut flood = Flood::new();
+        flood.offer(&header(1, 0), b"hi", 2, BROADCAST, 10);
+        flood.offer(&header(1, 0), b"hi", 2, BROADCAST, 10);
+        assert!(flood.due(9).is_none());
+        let relayed = flood.due(10).unwrap();
+        let frame = frame::decode(&relayed).unwrap();
+        assert_eq!(frame.header.ttl, header(1, 0).ttl - 1);
+        assert_eq!(frame.header.via, 2);
+        assert_eq!(frame.payload, b"hi");
+        assert!(flood.due(10).is_none());
+        assert_eq!(flood.relayed, 1);
+    }
+
+    #[test]
+    fn sealed_frames_differ_by_epoch() {
+        let mut flood = Flood::new();
+        let sealed = header(1, FLAG_ENCRYPTED);
+        flood.offer(&sealed, &[0, 0, 0xaa], 2, BROADCAST, 0);
+        flood.offer(&sealed, &[0, 0, 0xbb], 2, BROADCAST, 0);
+        // the sender rebooted in the next epoch and started its seq over
+        flood.offer(&sealed, &[1, 0, 0xcc], 2, BROADCAST, 0);
+        assert_eq!(
+            frame::decode(&flood.due(0).unwrap()).unwrap().payload,
+            [0, 0, 0xaa]
+        );
+        assert_eq!(
+            frame::decode(&flood.due(0).unwrap()).unwrap().payload,
+            [1, 0, 0xcc]
+        );
+        assert!(flood.due(0).is_none());
+    }
+
+    #[test]
+    fn last_hop_and_disabled_relays_stay_quiet() {
+        let mut flood = Flood::new();
+        let last = Header {
+            ttl: 0,
+            ..header(1, 0)
+        };
+        flood.offer(&last, b"hi", 2, BROADCAST, 0);
+        flood.enabled = false;
+        flood.offer(&header(2, 0), b"hi", 2, BROADCAST, 0);
+        assert!(flood.due(0).is_none());
+    }
+}
@@ -0,0 +1,250 @@
+//! Nodes in direct range and the presence beacons announcing them.
+//!
+//! Every node periodically broadcasts a [`Kind::Presence`] frame that is
+//! never relayed, its payload being:
+//!
+//! ```text
+//! | battery (1) | version (3) | name ...
+//! ```
+//! `battery` is a percentage, `0xFF` when the node cannot measure it.
+//!
+//! [`Kind::Presence`]: crate::frame::Kind::Presence
+#![allow(dead_code)]
+
+use heapless::{String, Vec};
+
+use crate::frame::Address;
+use crate::state::reached;
+
+pub const MAX_NEIGHBOURS: usize = 8;
+/// Neighbours not heard for this long left our range.
+pub const NEIGHBOUR_TIMEOUT_MS: u32 = 600_000;
+/// Default time between two beacons.
+pub const BEACON_INTERVAL_MS: u32 = 120_000;
+pub const NAME_LEN: usize = 12;
+const NO_BATTERY: u8 = 0xFF;
+
+/// Version of this firmware, from the crate version.
+pub const FIRMWARE_VERSION: [u8; 3] = [
+    parse_u8(env!("CARGO_PKG_VERSION_MAJOR")),
+    parse_u8(env!("CARGO_PKG_VERSION_MINOR")),
+    parse_u8(env!("CARGO_PKG_VERSION_PATCH")),
+];
+
+const fn parse_u8(s: &str) -> u8 {
+    let bytes = s.as_bytes();
+    let mut value: u8 = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        value = value * 10 + (bytes[i] - b'0');
+        i += 1;
+    }
+    value
+}
+
+/// Voltage of a single cell LiPo and its charge, from full to empty.
+const DISCHARGE: [(u32, u8); 8] = [
+    (4_200, 100),
+    (4_100, 90),
+    (4_000, 80),
+    (3_900, 60),
+    (3_800, 40),
+    (3_700, 20),
+    (3_600, 10),
+    (3_300, 0),
+];
+/// Above this the node is powered through USB rather than its battery.
+const EXTERNAL_MV: u32 = 4_400;
+
+/// Charge in percent of the battery at `millivolts`, `None` when running
+/// from USB.
+pub fn battery_percent(millivolts: u32) -> Option<u8> {
+    if millivolts > EXTERNAL_MV {
+        return None;
+    }
+    let mut upper = DISCHARGE[0];
+    for lower in DISCHARGE {
+        if millivolts >= lower.0 {
+            if lower == upper {
+                return Some(upper.1);
+            }
+            let span = (upper.1 - lower.1) as u32;
+            let above = (millivolts - lower.0) * span / (upper.0 - lower.0);
+            return Some(lower.1 + above as u8);
+        }
+        upper = lower;
+    }
+    Some(0)
+}
+
+/// VSYS in millivolts from a 12 bit reading of the Pico's ADC3, which sees
+/// it through a 1/3 divider against the 3.3 V reference.
+pub fn vsys_millivolts(raw: u16) -> u32 {
+    raw as u32 * 3 * 3_300 / 4_096
+}
+
+/// What a node tells about itself.
+#[derive(Clone, PartialEq, Default)]
+pub struct Presence {
+    pub name: String<NAME_LEN>,
+    pub battery: Option<u8>,
+    pub version: [u8; 3],
+}
+
+impl Presence {
+    /// Write the beacon payload to `out`, returning its length.
+    pub fn encode(&self, out: &mut [u8]) -> usize {
+        let len = 4 + self.name.len();
+        if out.len() < len {
+            return 0;
+        }
+        out[0] = self.battery.unwrap_or(NO_BATTERY);
+        out[1..4].copy_from_slice(&self.version);
+        out[4..len].copy_from_slice(self.name.as_bytes());
+        len
+    }
+
+    pub fn decode(payload: &[u8]) -> Option<Self> {
+        if payload.len() < 4 {
+            return None;
+        }
+        let name = core::str::from_utf8(&payload[4..]).ok()?;
+        let mut presence = Presence {
+            name: String::new(),
+            battery: Some(payload[0]).filter(|&battery| battery != NO_BATTERY),
+            version: [payload[1], payload[2], payload[3]],
+        };
+        presence.name.push_str(name).ok()?;
+        Some(presence)
+    }
+}
+
+#[derive(Clone)]
+pub struct Neighbour {
+    pub address: Address,
+    /// Known once its beacon was received.
+    pub presence: Option<Presence>,
+    pub last_heard: u32,
+    pub rssi: i16,
+    pub snr: Option<i16>,
+}
+
+pub struct Neighbours {
+    neighbours: Vec<Neighbour, MAX_NEIGHBOURS>,
+}
+
+impl Neighbours {
+    pub fn new() -> Self {
+        Self {
+            neighbours: Vec::new(),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Neighbour> {
+        self.neighbours.iter()
+    }
+
+    /// Name `address` announced in its beacon.
+    pub fn name(&self, address: Address) -> Option<&str> {
+        self.neighbours
+            .iter()
+            .find(|n| n.address == address)
+            .and_then(|n| n.presence.as_ref())
+            .map(|presence| presence.name.as_str())
+            .filter(|name| !name.is_empty())
+    }
+
+    /// Address of the neighbour announcing `name`.
+    pub fn address(&self, name: &str) -> Option<Address> {
+        self.neighbours
+            .iter()
+            .find(|n| n.presence.as_ref().map_or(false, |p| p.name == name))
+            .map(|n| n.address)
+    }
+
+    /// Forget the neighbours not heard recently.
+    pub fn expire(&mut self, now: u32) {
+        self.neighbours
+            .retain(|n| !reached(now, n.last_heard.wrapping_add(NEIGHBOUR_TIMEOUT_MS)));
+    }
+
+    /// Record a packet received directly from `address`.
+    pub fn heard(&mut self, address: Address, rssi: i16, snr: Option<i16>, now: u32) {
+        self.expire(now);
+        let neighbour = match self.neighbours.iter().position(|n| n.address == address) {
+            Some(i) => &mut self.neighbours[i],
+            None => {
+                if self.neighbours.is_full() {
+                    let oldest = self
+                        .neighbours
+                        .iter()
+                        .enumerate()
+                        .max_by_key(|(_, n)| now.wrapping_sub(n.last_heard))
+                        .map(|(i, _)| i)
+                        .unwrap_or(0);
+                    self.neighbours.swap_remove(oldest);
+                }
+                _ = self.neighbours.push(Neighbour {
+                    address,
+                    presence: None,
+                    last_heard: now,
+                    rssi,
+                    snr,
+                });
+                return;
+            }
+        };
+        neighbour.last_heard = now;
+        neighbour.rssi = rssi;
+        neighbour.snr = snr;
+    }
+
+    /// Record the beacon of `address`, heard just before with [`Neighbours::heard`].
+    pub fn announced(&mut self, address: Address, presence: Presence) {
+        if let Some(neighbour) = self.neighbours.iter_mut().find(|n| n.address == address) {
+            neighbour.presence = Some(presence);
+        }
+    }
+}
+
+impl Default for Neighbours {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn battery_charge() {
+        assert_eq!(battery_percent(5_000), None);
+        assert_eq!(battery_percent(4_300), Some(100));
+        assert_eq!(battery_percent(4_200), Some(100));
+        assert_eq!(battery_percent(4_150), Some(95));
+        assert_eq!(battery_percent(3_850), Some(50));
+        assert_eq!(battery_percent(3_450), Some(5));
+        assert_eq!(battery_percent(3_300), Some(0));
+        assert_eq!(battery_percent(2_900), Some(0));
+        // 3.6 V on VSYS is 1.2 V at the pin
+        assert_eq!(vsys_millivolts(1_489), 3_598);
+    }
+
+    #[test]
+    fn presence_round_trip() {
+        let mut presence = Presence {
+            battery: Some(42),
+            version: [1, 2, 3],
+            ..Default::default()
+        };
+        _ = presence.name.push_str("ada");
+        let mut payload = [0u8; 16];
+        let len = presence.encode(&mut payload);
+        assert_eq!(payload[..len], [42, 1, 2, 3, b'a', b'd', b'a']);
+        assert!(Presence::decode(&payload[..len]) == Some(presence.clone()));
+        presence.battery = None;
+        presence.encode(&mut payload);
+        assert!(Presence::decode(&payload[..len]) == Some(presence));
+    }
+}
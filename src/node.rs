@@ -0,0 +1,286 @@
+#![allow(dead_code)]
+
+use heapless::{Deque, Vec};
+
+use crate::crypto::{self, CryptoError, Key};
+use crate::duty::DutyCycle;
+use crate::fragment::Reassembly;
+use crate::frame::{address_name, Address, Header, FLAG_ENCRYPTED};
+use crate::lbt::Backoff;
+use crate::mesh::Flood;
+use crate::neighbours::{Neighbours, Presence, BEACON_INTERVAL_MS, FIRMWARE_VERSION};
+use crate::replay::ReplayWindow;
+use crate::routes::RouteTable;
+use crate::settings::{RadioSettings, SettingsError};
+use crate::state::{reached, Packet, MAX_PACKET};
+
+/// Messages waiting for an acknowledgement at the same time.
+pub const MAX_PENDING: usize = 4;
+/// Time to wait for an ack before sending again, on top of the airtime of
+/// the message and of the ack.
+pub const ACK_TIMEOUT_MS: u32 = 3_000;
+/// Retransmissions before a message is reported as failed.
+pub const MAX_RETRIES: u8 = 3;
+/// Sequence numbers left in an epoch when the next one is reserved, more
+/// than the frames sent between two turns of the main loop.
+pub const SEQ_RESERVE: u16 = 1_024;
+
+/// A message we sent and are waiting an acknowledgement for.
+pub struct Pending {
+    pub seq: u16,
+    /// Epoch the message was first sealed with, resent frames must reuse it.
+    pub epoch: Option<u16>,
+    /// Recipient, [`crate::frame::BROADCAST`] for the whole channel.
+    pub to: Address,
+    pub body: Packet,
+    pub retries: u8,
+    pub deadline: u32,
+}
+
+/// A message too long for one frame, sent a fragment at a time.
+pub struct Burst {
+    /// Sequence number of the first fragment.
+    pub seq: u16,
+    pub epoch: Option<u16>,
+    pub to: Address,
+    pub flags: u8,
+    pub body: Packet,
+    /// Index of the next fragment to send.
+    pub next: u8,
+}
+
+/// Identity and protocol state of this node.
+pub struct Node {
+    pub address: Address,
+    /// What our beacons announce.
+    pub presence: Presence,
+    /// Time between our beacons, `0` to stay silent.
+    pub beacon_interval_ms: u32,
+    /// Time our next beacon is sent.
+    pub beacon_at: u32,
+    /// Nodes in direct range.
+    pub neighbours: Neighbours,
+    /// Ask receivers to acknowledge our chat messages.
+    pub want_ack: bool,
+    /// Parameters the radio runs with, change them with [`Node::set_radio`].
+    radio: RadioSettings,
+    /// The radio settings changed and are applied when the radio is idle.
+    pub reconfigure: bool,
+    /// Radio resets since a frame last went out or came in, see
+    /// [`crate::state::State::on_error`].
+    pub resets: u8,
+    /// Channel key, frames are sent and expected sealed when set.
+    pub key: Option<Key>,
+    /// High half of the nonce counter, must never repeat for a given key.
+    /// Reserved in flash by [`crate::settings::reserve_epoch`] before use, sealed
+    /// frames are refused while there is none.
+    epoch: Option<u16>,
+    seq: u16,
+    pub pending: Vec<Pending, MAX_PENDING>,
+    /// Acks we owe, as (destination, acknowledged seq).
+    pub acks: Deque<(Address, u16), 4>,
+    /// Frame ready to go out once the channel is clear.
+    pub outgoing: Option<Vec<u8, MAX_PACKET>>,
+    pub backoff: Backoff,
+    /// Airtime spent in the last hour.
+    pub duty: DutyCycle,
+    /// Fragments of a message left to send.
+    pub burst: Option<Burst>,
+    /// Fragments of messages received so far.
+    pub reassembly: Reassembly,
+    /// Frames of other nodes waiting to be relayed.
+    pub mesh: Flood,
+    /// Next hops towards the nodes we heard of.
+    pub routes: RouteTable,
+    rng: u32,
+    /// Frames dropped because they failed authentication.
+    pub rejected: u32,
+    /// Frames already seen from each peer.
+    pub replay: ReplayWindow,
+    /// Frames dropped because they were received before.
+    pub duplicates: u32,
+}
+
+impl Node {
+    pub fn new(address: Address) -> Self {
+        let mut presence = Presence {
+            version: FIRMWARE_VERSION,
+            ..Default::default()
+        };
+        _ = presence.name.push_str(&address_name(address));
+        Self {
+            address,
+            presence,
+            beacon_interval_ms: BEACON_INTERVAL_MS,
+            beacon_at: 0,
+            neighbours: Neighbours::new(),
+            want_ack: true,
+            radio: RadioSettings::default(),
+            reconfigure: false,
+            resets: 0,
+            key: None,
+            epoch: None,
+            seq: 0,
+            pending: Vec::new(),
+            acks: Deque::new(),
+            outgoing: None,
+            backoff: Backoff::new(),
+            duty: DutyCycle::default(),
+            burst: None,
+            reassembly: Reassembly::new(),
+            mesh: Flood::new(),
+            routes: RouteTable::new(),
+            // the address makes the jitter of each node different
+            rng: (address as u32).max(1),
+            rejected: 0,
+            replay: ReplayWindow::new(),
+            duplicates: 0,
+        }
+    }
+
+    /// Name announced to neighbours, cut to what fits in a beacon.
+    pub fn set_name(&mut self, name: &str) {
+        self.presence.name.clear();
+        for c in name.chars() {
+            if self.presence.name.push(c).is_err() {
+                break;
+            }
+        }
+    }
+
+    pub fn radio(&self) -> &RadioSettings {
+        &self.radio
+    }
+
+    /// Switch the radio to `settings` once idle, when they are legal.
+    pub fn set_radio(&mut self, settings: RadioSettings) -> Result<(), SettingsError> {
+        settings.validate()?;
+        if settings != self.radio {
+            self.radio = settings;
+            self.reconfigure = true;
+        }
+        Ok(())
+    }
+
+    pub fn epoch(&self) -> Option<u16> {
+        self.epoch
+    }
+
+    /// Start numbering frames in `epoch`, which must have been reserved so
+    /// that no other boot uses it, `None` when none could be.
+    pub fn set_epoch(&mut self, epoch: Option<u16>) {
+        self.epoch = epoch;
+        self.seq = 0;
+    }
+
+    /// `true` when the sequence numbers of the epoch are nearly all used and
+    /// the next one should be reserved.
+    pub fn epoch_spent(&self) -> bool {
+        self.epoch.is_some() && self.seq > u16::MAX - SEQ_RESERVE
+    }
+
+    /// Sequence number for the next frame we originate.
+    pub fn next_seq(&mut self) -> u16 {
+        self.next_seqs(1)
+    }
+
+    /// First of `count` consecutive sequence numbers, all in the same epoch.
+    pub fn next_seqs(&mut self, count: u16) -> u16 {
+        if self.seq.checked_add(count).is_none() {
+            // the nonces of this epoch are used up, sealing waits for a new one
+            self.epoch = None;
+            self.seq = 0;
+        }
+        let first = self.seq + 1;
+        self.seq += count;
+        first
+    }
+
+    /// Authenticate and decrypt a received payload as the channel requires,
+    /// returning the sender's epoch with it, `0` for frames sent in clear.
+    pub fn open<'a>(
+        &self,
+        header: &Header,
+        payload: &'a [u8],
+        out: &'a mut [u8],
+    ) -> Result<(u16, &'a [u8]), CryptoError> {
+        match (&self.key, header.flags & FLAG_ENCRYPTED != 0) {
+            (Some(key), true) => crypto::open(key, header, payload, out),
+            (None, false) => Ok((0, payload)),
+            _ => Err(CryptoError::Authentication),
+        }
+    }
+
+    /// Pseudo random number for jitters, not for cryptography.
+    pub fn random(&mut self) -> u32 {
+        // xorshift32
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        self.rng
+    }
+
+    /// Queue an acknowledgement, once even if the message came several times.
+    pub fn owe_ack(&mut self, to: Address, seq: u16) {
+        if !self.acks.iter().any(|&ack| ack == (to, seq)) {
+            _ = self.acks.push_back((to, seq));
+        }
+    }
+
+    /// Index of a pending message whose ack timed out.
+    pub fn expired(&self, now: u32) -> Option<usize> {
+        self.pending.iter().position(|p| reached(now, p.deadline))
+    }
+
+    /// Forget the pending message `seq`, returning `true` if it was waiting.
+    pub fn acknowledge(&mut self, seq: u16) -> bool {
+        match self.pending.iter().position(|p| p.seq == seq) {
+            Some(i) => {
+                self.pending.swap_remove(i);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sequence_numbers_stay_in_one_epoch() {
+        let mut node = Node::new(1);
+        assert_eq!(node.epoch(), None);
+        assert!(!node.epoch_spent());
+        node.set_epoch(Some(5));
+        assert_eq!(node.next_seqs(3), 1);
+        assert_eq!(node.next_seq(), 4);
+        assert_eq!(node.epoch(), Some(5));
+    }
+
+    #[test]
+    fn spent_epoch_is_renewed_before_it_overflows() {
+        let mut node = Node::new(1);
+        node.set_epoch(Some(5));
+        node.seq = u16::MAX - SEQ_RESERVE;
+        assert!(!node.epoch_spent());
+        node.next_seq();
+        assert!(node.epoch_spent());
+        node.set_epoch(Some(6));
+        assert!(!node.epoch_spent());
+        assert_eq!(node.next_seq(), 1);
+    }
+
+    #[test]
+    fn overflowing_epoch_is_dropped() {
+        let mut node = Node::new(1);
+        node.set_epoch(Some(5));
+        node.seq = u16::MAX - 2;
+        assert_eq!(node.next_seqs(2), u16::MAX - 1);
+        // the burst does not fit, its nonces would repeat in epoch 5
+        assert_eq!(node.next_seqs(3), 1);
+        assert_eq!(node.epoch(), None);
+        assert!(!node.epoch_spent());
+    }
+}
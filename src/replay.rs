@@ -0,0 +1,192 @@
+//! Detection of frames received more than once.
+//!
+//! Every peer gets a sliding window over its frame counter, the epoch and
+//! sequence number joined in a `u32`. A counter already marked in the window
+//! is a duplicate, either a retransmission or a replayed capture.
+//!
+//! Only [`MAX_PEERS`] windows fit, the newest counter of a peer whose window
+//! is dropped stays as its floor so that replaying its old frames does not
+//! pass for a new peer. Eviction can only be forced by more than
+//! [`MAX_FLOORS`] senders holding the channel key.
+#![allow(dead_code)]
+
+use heapless::{Deque, Vec};
+
+use crate::frame::Address;
+
+/// Peers tracked at the same time, the one heard longest ago is forgotten.
+pub const MAX_PEERS: usize = 8;
+/// Counters behind the newest one still remembered for a peer.
+pub const WINDOW: u32 = 32;
+/// Peers remembered by their newest counter once their window is dropped.
+pub const MAX_FLOORS: usize = 64;
+
+struct Peer {
+    address: Address,
+    /// Newest counter seen.
+    top: u32,
+    /// Bit `n` set when `top - n` was seen.
+    seen: u32,
+    last_heard: u32,
+}
+
+pub struct ReplayWindow {
+    peers: Vec<Peer, MAX_PEERS>,
+    /// Address and newest counter of the peers dropped, oldest first.
+    floors: Deque<(Address, u32), MAX_FLOORS>,
+}
+
+/// Counter of a frame, ordering frames of one sender across epochs.
+pub fn counter(epoch: u16, seq: u16) -> u32 {
+    (epoch as u32) << 16 | seq as u32
+}
+
+impl ReplayWindow {
+    pub fn new() -> Self {
+        Self {
+            peers: Vec::new(),
+            floors: Deque::new(),
+        }
+    }
+
+    /// Record `counter` from `address`, returning `false` if it is a duplicate.
+    ///
+    /// Counters older than the window, or not above the floor of a dropped
+    /// peer, cannot be told apart from replays. They are refused when
+    /// `strict`, which only makes sense for authenticated frames. Otherwise
+    /// the peer most likely restarted and its window starts over from
+    /// `counter`.
+    pub fn check(&mut self, address: Address, counter: u32, strict: bool, now: u32) -> bool {
+        let peer = match self.peers.iter().position(|p| p.address == address) {
+            Some(i) => &mut self.peers[i],
+            None => {
+                if strict && self.floor(address).map_or(false, |top| counter <= top) {
+                    return false;
+                }
+                if self.peers.is_full() {
+                    let oldest = self
+                        .peers
+                        .iter()
+                        .enumerate()
+                        .max_by_key(|(_, p)| now.wrapping_sub(p.last_heard))
+                        .map(|(i, _)| i)
+                        .unwrap_or(0);
+                    let dropped = self.peers.swap_remove(oldest);
+                    self.set_floor(dropped.address, dropped.top);
+                }
+                _ = self.peers.push(Peer {
+                    address,
+                    top: counter,
+                    seen: 1,
+                    last_heard: now,
+                });
+                return true;
+            }
+        };
+        peer.last_heard = now;
+        if counter > peer.top {
+            // shifting by the whole width forgets everything
+            peer.seen = peer.seen.checked_shl(counter - peer.top).unwrap_or(0) | 1;
+            peer.top = counter;
+            return true;
+        }
+        let age = peer.top - counter;
+        if age >= WINDOW {
+            if strict {
+                return false;
+            }
+            peer.top = counter;
+            peer.seen = 1;
+            return true;
+        }
+        let bit = 1 << age;
+        if peer.seen & bit != 0 {
+            return false;
+        }
+        peer.seen |= bit;
+        true
+    }
+
+    fn floor(&self, address: Address) -> Option<u32> {
+        self.floors
+            .iter()
+            .find(|(a, _)| *a == address)
+            .map(|&(_, top)| top)
+    }
+
+    fn set_floor(&mut self, address: Address, top: u32) {
+        if let Some(floor) = self.floors.iter_mut().find(|(a, _)| *a == address) {
+            floor.1 = top;
+            return;
+        }
+        if self.floors.is_full() {
+            self.floors.pop_front();
+        }
+        _ = self.floors.push_back((address, top));
+    }
+}
+
+impl Default for ReplayWindow {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn duplicates_in_the_window() {
+        let mut replay = ReplayWindow::new();
+        assert!(replay.check(1, 10, true, 0));
+        assert!(!replay.check(1, 10, true, 0));
+        assert!(replay.check(1, 12, true, 0));
+        // out of order but never seen
+        assert!(replay.check(1, 11, true, 0));
+        assert!(!replay.check(1, 11, true, 0));
+        assert!(replay.check(1, 12 + WINDOW, true, 0));
+        assert!(!replay.check(1, 12, true, 0));
+        // another peer has its own window
+        assert!(replay.check(2, 10, true, 0));
+    }
+
+    #[test]
+    fn old_counters_restart_only_clear_peers() {
+        let mut replay = ReplayWindow::new();
+        assert!(replay.check(1, counter(3, 1), false, 0));
+        assert!(replay.check(1, counter(0, 1), false, 0));
+        assert!(!replay.check(1, counter(0, 1), false, 0));
+        assert!(replay.check(2, counter(3, 1), true, 0));
+        assert!(!replay.check(2, counter(0, 1), true, 0));
+    }
+
+    #[test]
+    fn dropped_peer_keeps_a_floor() {
+        let mut replay = ReplayWindow::new();
+        assert!(replay.check(1, counter(2, 5), true, 0));
+        for peer in 2..=MAX_PEERS as Address + 1 {
+            assert!(replay.check(peer, 1, true, peer as u32));
+        }
+        // the window of peer 1 was dropped to make room
+        assert!(!replay.check(1, counter(2, 5), true, 100));
+        assert!(!replay.check(1, counter(2, 4), true, 100));
+        assert!(!replay.check(1, counter(1, 9), true, 100));
+        assert!(replay.check(1, counter(2, 6), true, 100));
+        assert!(!replay.check(1, counter(2, 6), true, 100));
+    }
+
+    #[test]
+    fn floors_follow_the_newest_window() {
+        let mut replay = ReplayWindow::new();
+        for round in 1..4 {
+            for peer in 0..=MAX_PEERS as Address {
+                let now = (round * 100 + peer) as u32;
+                assert!(replay.check(peer, counter(round, 1), true, now));
+            }
+        }
+        for peer in 0..=MAX_PEERS as Address {
+            assert!(!replay.check(peer, counter(2, 1), true, 1_000));
+        }
+    }
+}
@@ -0,0 +1,291 @@
+//! Distance-vector routing of frames addressed to a single node.
+//!
+//! Routes are learned from the `src` and `via` fields of every frame heard,
+//! and from the tables neighbours advertise in [`Kind::Routes`] frames:
+//!
+//! ```text
+//! | dst (2) | next (2) | hops (1) | dst (2) | next (2) | hops (1) ...
+//! ```
+//! A neighbour ignores the routes it is itself the next hop of, so two nodes
+//! never learn from each other ever longer routes to one that left. A frame
+//! for a node without a route is flooded, see [`crate::mesh`].
+//!
+//! [`Kind::Routes`]: crate::frame::Kind::Routes
+#![allow(dead_code)]
+
+use heapless::Vec;
+
+use crate::frame::{Address, BROADCAST, DEFAULT_TTL};
+use crate::state::reached;
+
+pub const MAX_ROUTES: usize = 16;
+/// Routes not confirmed for this long are forgotten.
+pub const ROUTE_TIMEOUT_MS: u32 = 300_000;
+/// Time between two advertisements of our table.
+pub const ADVERTISE_MS: u32 = 60_000;
+/// Routes longer than this are not worth keeping, flooding reaches as far.
+pub const MAX_HOPS: u8 = DEFAULT_TTL + 1;
+const ENTRY_LEN: usize = 5;
+
+#[derive(Clone, Copy, PartialEq)]
+pub struct Route {
+    pub dst: Address,
+    /// Neighbour to hand frames for `dst` to.
+    pub next: Address,
+    pub hops: u8,
+    /// Time the route was last confirmed.
+    pub heard: u32,
+}
+
+pub struct RouteTable {
+    routes: Vec<Route, MAX_ROUTES>,
+    /// Time our table is next advertised.
+    pub advertise_at: u32,
+}
+
+impl RouteTable {
+    pub fn new() -> Self {
+        Self {
+            routes: Vec::new(),
+            advertise_at: 0,
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Route> {
+        self.routes.iter()
+    }
+
+    /// Neighbour to send a frame for `dst` through, [`BROADCAST`] to flood it.
+    pub fn next_hop(&self, dst: Address) -> Address {
+        if dst == BROADCAST {
+            return BROADCAST;
+        }
+        self.routes
+            .iter()
+            .find(|route| route.dst == dst)
+            .map_or(BROADCAST, |route| route.next)
+    }
+
+    /// Forget the routes that were not confirmed recently.
+    pub fn expire(&mut self, now: u32) {
+        self.routes
+            .retain(|route| !reached(now, route.heard.wrapping_add(ROUTE_TIMEOUT_MS)));
+    }
+
+    /// Take note that `dst` is `hops` away through the neighbour `next`.
+    ///
+    /// A shorter route replaces the known one, a route through the same
+    /// neighbour is refreshed with its new length, or forgotten when it
+    /// grew longer than [`MAX_HOPS`].
+    pub fn learn(&mut self, dst: Address, next: Address, hops: u8, now: u32) {
+        if dst == BROADCAST || next == BROADCAST {
+            return;
+        }
+        let route = Route {
+            dst,
+            next,
+            hops,
+            heard: now,
+        };
+        if let Some(i) = self.routes.iter().position(|route| route.dst == dst) {
+            let known = &mut self.routes[i];
+            if hops > MAX_HOPS {
+                if next == known.next {
+                    self.routes.swap_remove(i);
+                }
+            } else if hops <= known.hops || next == known.next {
+                *known = route;
+            }
+            return;
+        }
+        if hops > MAX_HOPS {
+            return;
+        }
+        if self.routes.is_full() {
+            // make room by dropping the longest, then oldest, route
+            let worst = self
+                .routes
+                .iter()
+                .enumerate()
+                .max_by_key(|(_, r)| (r.hops, now.wrapping_sub(r.heard)))
+                .map(|(i, _)| i)
+                .unwrap_or(0);
+            if self.routes[worst].hops < hops {
+                return;
+            }
+            self.routes.swap_remove(worst);
+        }
+        _ = self.routes.push(route);
+    }
+
+    /// Learn the routes advertised by the neighbour `from`, `own` being our
+    /// address. The ones going through us are taken as unreachable.
+    pub fn learn_advertisement(&mut self, own: Address, from: Address, payload: &[u8], now: u32) {
+        for entry in payload.chunks_exact(ENTRY_LEN) {
+            let dst = u16::from_le_bytes([entry[0], entry[1]]);
+            let next = u16::from_le_bytes([entry[2], entry[3]]);
+            let hops = if next == own {
+                MAX_HOPS + 1
+            } else {
+                entry[4].saturating_add(1)
+            };
+            if dst != own {
+                self.learn(dst, from, hops, now);
+            }
+        }
+    }
+
+    /// Write our table for neighbours into `out`, returning its length.
+    /// `own` is advertised first, zero hops away.
+    pub fn advertisement(&self, own: Address, out: &mut [u8]) -> usize {
+        let own = Route {
+            dst: own,
+            next: own,
+            hops: 0,
+            heard: 0,
+        };
+        let mut len = 0;
+        for route in core::iter::once(&own).chain(self.routes.iter()) {
+            if len + ENTRY_LEN > out.len() {
+                break;
+            }
+            let entry = &mut out[len..len + ENTRY_LEN];
+            entry[..2].copy_from_slice(&route.dst.to_le_bytes());
+            entry[2..4].copy_from_slice(&route.next.to_le_bytes());
+            entry[4] = route.hops;
+            len += ENTRY_LEN;
+        }
+        len
+    }
+}
+
+impl Default for RouteTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u32 = 1_000;
+
+    fn route(table: &RouteTable, dst: Address) -> Option<(Address, u8)> {
+        table
+            .iter()
+            .find(|route| route.dst == dst)
+            .map(|route| (route.next, route.hops))
+    }
+
+    #[test]
+    fn shorter_route_replaces_the_known_one() {
+        let mut table = RouteTable::new();
+        table.learn(9, 2, 3, NOW);
+        table.learn(9, 3, 4, NOW);
+        assert_eq!(route(&table, 9), Some((2, 3)));
+        table.learn(9, 3, 2, NOW);
+        assert_eq!(route(&table, 9), Some((3, 2)));
+        assert_eq!(table.next_hop(9), 3);
+        assert_eq!(table.next_hop(8), BROADCAST);
+    }
+
+    #[test]
+    fn same_next_hop_refreshes_the_route() {
+        let mut table = RouteTable::new();
+        table.learn(9, 2, 1, NOW);
+        table.learn(9, 2, 3, NOW + 10);
+        let known = table.iter().next().unwrap();
+        assert_eq!((known.next, known.hops, known.heard), (2, 3, NOW + 10));
+    }
+
+    #[test]
+    fn poisoned_route_through_the_next_hop_is_forgotten() {
+        let mut table = RouteTable::new();
+        table.learn(9, 2, 2, NOW);
+        // another neighbour cannot take it away
+        table.learn(9, 3, MAX_HOPS + 1, NOW);
+        assert_eq!(route(&table, 9), Some((2, 2)));
+        table.learn(9, 2, MAX_HOPS + 1, NOW);
+        assert_eq!(route(&table, 9), None);
+        table.learn(9, 2, MAX_HOPS + 1, NOW);
+        assert_eq!(route(&table, 9), None);
+    }
+
+    #[test]
+    fn full_table_drops_the_worst_route() {
+        let mut table = RouteTable::new();
+        for dst in 0..MAX_ROUTES as Address {
+            // 5 is the longest and, among the two hop ones, 7 the oldest
+            let hops = match dst {
+                5 => 3,
+                7 => 2,
+                _ => 1,
+            };
+            let heard = if dst == 7 { NOW - 500 } else { NOW };
+            table.learn(dst, 100, hops, heard);
+        }
+        table.learn(50, 100, 4, NOW);
+        assert_eq!(route(&table, 50), None);
+        table.learn(50, 100, 2, NOW);
+        assert_eq!(route(&table, 50), Some((100, 2)));
+        assert_eq!(route(&table, 5), None);
+        table.learn(51, 100, 2, NOW);
+        assert_eq!(route(&table, 7), None);
+        assert_eq!(table.iter().count(), MAX_ROUTES);
+    }
+
+    #[test]
+    fn routes_expire_across_the_clock_wrap() {
+        let mut table = RouteTable::new();
+        let heard = u32::MAX - 1_000;
+        table.learn(9, 2, 1, heard);
+        table.expire(heard.wrapping_add(ROUTE_TIMEOUT_MS - 1));
+        assert_eq!(route(&table, 9), Some((2, 1)));
+        table.expire(heard.wrapping_add(ROUTE_TIMEOUT_MS));
+        assert_eq!(route(&table, 9), None);
+    }
+
+    #[test]
+    fn advertisement_round_trip() {
+        let mut a = RouteTable::new();
+        a.learn(3, 3, 1, NOW);
+        a.learn(4, 3, 2, NOW);
+        let mut payload = [0; 64];
+        let len = a.advertisement(1, &mut payload);
+        assert_eq!(len, 3 * ENTRY_LEN);
+        let mut b = RouteTable::new();
+        b.learn_advertisement(2, 1, &payload[..len], NOW);
+        assert_eq!(route(&b, 1), Some((1, 1)));
+        assert_eq!(route(&b, 3), Some((1, 2)));
+        assert_eq!(route(&b, 4), Some((1, 3)));
+        // what does not fit is left out
+        assert_eq!(a.advertisement(1, &mut payload[..ENTRY_LEN + 1]), ENTRY_LEN);
+    }
+
+    /// A, B and C in a line, C leaves: A and B must not keep feeding each
+    /// other routes to it.
+    #[test]
+    fn routes_are_not_learned_back_through_us() {
+        let (a, b, c) = (1, 2, 3);
+        let mut at_a = RouteTable::new();
+        let mut at_b = RouteTable::new();
+        at_b.learn(c, c, 1, NOW);
+        let mut payload = [0; 64];
+        let len = at_b.advertisement(b, &mut payload);
+        at_a.learn_advertisement(a, b, &payload[..len], NOW);
+        assert_eq!(route(&at_a, c), Some((b, 2)));
+        // B forgets C, A's route goes through B so B does not take it
+        at_b.expire(NOW + ROUTE_TIMEOUT_MS);
+        let len = at_a.advertisement(a, &mut payload);
+        at_b.learn_advertisement(b, a, &payload[..len], NOW + ROUTE_TIMEOUT_MS);
+        assert_eq!(route(&at_b, c), None);
+        assert_eq!(route(&at_b, a), Some((a, 1)));
+        // nobody confirms the route of A any more, it times out
+        let len = at_b.advertisement(b, &mut payload);
+        at_a.learn_advertisement(a, b, &payload[..len], NOW + ROUTE_TIMEOUT_MS);
+        at_a.expire(NOW + ROUTE_TIMEOUT_MS);
+        assert_eq!(route(&at_a, c), None);
+        assert_eq!(route(&at_a, b), Some((b, 1)));
+    }
+}
@@ -0,0 +1,159 @@
+//! Pages of node state shown in place of the message log.
+#![allow(dead_code)]
+
+use core::fmt::Write;
+
+use heapless::String;
+
+use crate::airtime;
+use crate::frame::address_name;
+use crate::interface::Page;
+use crate::node::Node;
+
+/// Messages longer than this on air are only sent once confirmed.
+pub const LONG_MESSAGE_MS: u32 = 2_000;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Screen {
+    /// The message log.
+    Chat,
+    Routes,
+    Neighbours,
+}
+
+impl Screen {
+    /// Show `screen`, or go back to the chat if it is already shown.
+    pub fn toggle(self, screen: Screen) -> Screen {
+        if self == screen {
+            Screen::Chat
+        } else {
+            screen
+        }
+    }
+
+    /// Content of the page, `None` for the message log.
+    pub fn render(self, node: &Node, now: u32) -> Option<Page> {
+        match self {
+            Screen::Chat => None,
+            Screen::Routes => Some(routes(node, now)),
+            Screen::Neighbours => Some(neighbours(node, now)),
+        }
+    }
+}
+
+/// Share of the hourly airtime budget left, for the title status.
+pub fn airtime_left(node: &mut Node, now: u32) -> String<4> {
+    let mut left = String::new();
+    _ = write!(left, "{}%", node.duty.remaining_percent(now));
+    left
+}
+
+/// Warning to show before sending a `len` bytes message that would hold
+/// the channel for long, `None` when it can go right away.
+pub fn long_message(node: &Node, len: usize) -> Option<String<22>> {
+    let radio = node.radio();
+    let airtime = airtime::message_ms(&radio.channel(), &radio.lora(), len);
+    if airtime <= LONG_MESSAGE_MS {
+        return None;
+    }
+    let mut warning = String::new();
+    _ = write!(
+        warning,
+        "{}.{}s on air, again?",
+        airtime / 1_000,
+        airtime % 1_000 / 100
+    );
+    Some(warning)
+}
+
+pub(crate) fn line(page: &mut Page, args: core::fmt::Arguments) {
+    let mut line = String::new();
+    _ = line.write_fmt(args);
+    _ = page.push(line);
+}
+
+fn routes(node: &Node, now: u32) -> Page {
+    let mut page = Page::new();
+    line(&mut page, format_args!("Dest  Via  Hop Age"));
+    for route in node.routes.iter() {
+        let age = now.wrapping_sub(route.heard) / 1_000;
+        line(
+            &mut page,
+            format_args!(
+                "{}  {} {:>3} {:>3}",
+                address_name(route.dst),
+                address_name(route.next),
+                route.hops,
+                age
+            ),
+        );
+    }
+    if page.len() == 1 {
+        line(&mut page, format_args!("none, flooding"));
+    }
+    page
+}
+
+fn neighbours(node: &Node, now: u32) -> Page {
+    let mut page = Page::new();
+    line(
+        &mut page,
+        format_args!(
+            "{:<4} {:>4} {:>3} {:>3} {:>3}",
+            "Name", "RSSI", "SNR", "Bat", "Age"
+        ),
+    );
+    for neighbour in node.neighbours.iter() {
+        let hex = address_name(neighbour.address);
+        let presence = neighbour.presence.as_ref();
+        let name = presence.map_or(hex.as_str(), |p| p.name.as_str());
+        let mut snr: String<3> = String::new();
+        if let Some(value) = neighbour.snr {
+            _ = write!(snr, "{}", value);
+        }
+        let mut battery: String<3> = String::new();
+        if let Some(value) = presence.and_then(|p| p.battery) {
+            _ = write!(battery, "{}", value);
+        }
+        let age = (now.wrapping_sub(neighbour.last_heard) / 1_000).min(999);
+        line(
+            &mut page,
+            format_args!(
+                "{:<4.4} {:>4} {:>3} {:>3} {:>3}",
+                name, neighbour.rssi, snr, battery, age
+            ),
+        );
+    }
+    if page.len() == 1 {
+        line(&mut page, format_args!("nobody in range"));
+    }
+    page
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::neighbours::Presence;
+    use crate::view::LINE_CHARS;
+
+    #[test]
+    fn pages_fit_the_screen() {
+        let mut node = Node::new(1);
+        let now = 10_000_000;
+        node.neighbours.heard(0xbeef, -137, Some(-20), now - 599_000);
+        let mut presence = Presence {
+            battery: Some(100),
+            ..Default::default()
+        };
+        _ = presence.name.push_str("bartholomew");
+        node.neighbours.announced(0xbeef, presence);
+        node.routes.learn(0xbeef, 0x1234, 3, now);
+        for screen in [Screen::Neighbours, Screen::Routes] {
+            let page = screen.render(&node, now).unwrap();
+            assert_eq!(page.len(), 2);
+            for line in page.iter() {
+                assert!(line.chars().count() <= LINE_CHARS, "{}", line);
+            }
+        }
+    }
+}
@@ -0,0 +1,362 @@
+//! Radio parameters chosen at runtime.
+//!
+//! The defaults are the constants of [`crate::sx127x`], [`RadioSettings`]
+//! is checked against the limits of the band before reaching the driver and
+//! stored as:
+//!
+//! ```text
+//! | freq (4) | bw | sf | cr | preamble (2) | sync word | power |
+//! ```
+//! `bw`, `sf` and `cr` being indexes in [`BANDWIDTHS`],
+//! [`SPREADING_FACTORS`] and [`CODING_RATES`].
+//!
+//! [`save`] and [`load`] keep them in the [`Store`] with the other settings
+//! of the node.
+#![allow(dead_code)]
+
+use radio_sx127x::device::lora::{Bandwidth, CodingRate, LoRaChannel, LoRaConfig, SpreadingFactor};
+use radio_sx127x::device::{self, Channel, Modem, PaConfig};
+
+use crate::crypto::KEY_LEN;
+use crate::interface::Interface;
+use crate::node::Node;
+use crate::store::{Flash, Store, StoreError};
+use crate::sx127x::{CONFIG_CH, CONFIG_LORA, CONFIG_PA, CONFIG_RADIO};
+
+/// Keys of the settings in the [`Store`].
+pub const KEY_RADIO: u8 = 1;
+pub const KEY_NAME: u8 = 2;
+/// Empty when traffic is sent in clear.
+pub const KEY_CHANNEL_KEY: u8 = 3;
+pub const KEY_SIGNAL: u8 = 4;
+/// Last epoch reserved, whatever the channel key was.
+pub const KEY_EPOCH: u8 = 5;
+
+/// ETSI EN 300 220 band around [`crate::sx127x::FREQUENCY`], the whole
+/// channel must fit in it.
+pub const BAND_MIN_HZ: u32 = 433_050_000;
+pub const BAND_MAX_HZ: u32 = 434_790_000;
+/// 10 mW ERP allowed in the band.
+pub const MAX_POWER_DBM: u8 = 10;
+pub const MIN_POWER_DBM: u8 = 1;
+/// Shortest preamble the modem can detect.
+pub const MIN_PREAMBLE: u16 = 6;
+/// Private networks sync word, `0x34` being LoRaWAN's.
+pub const DEFAULT_SYNC_WORD: u8 = 0x12;
+pub const ENCODED_LEN: usize = 12;
+
+pub const BANDWIDTHS: [Bandwidth; 3] = [
+    Bandwidth::Bw125kHz,
+    Bandwidth::Bw250kHz,
+    Bandwidth::Bw500kHz,
+];
+
+/// SF6 needs an implicit header, which our variable length frames cannot use.
+pub const SPREADING_FACTORS: [SpreadingFactor; 6] = [
+    SpreadingFactor::Sf7,
+    SpreadingFactor::Sf8,
+    SpreadingFactor::Sf9,
+    SpreadingFactor::Sf10,
+    SpreadingFactor::Sf11,
+    SpreadingFactor::Sf12,
+];
+
+pub const CODING_RATES: [CodingRate; 4] = [
+    CodingRate::Cr4_5,
+    CodingRate::Cr4_6,
+    CodingRate::Cr4_7,
+    CodingRate::Cr4_8,
+];
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SettingsError {
+    /// The channel does not fit in the band.
+    Frequency,
+    SpreadingFactor,
+    Preamble,
+    Power,
+    /// A channel key that is not 64 hex digits.
+    Key,
+    /// A stored record that cannot be read back.
+    Corrupted,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct RadioSettings {
+    pub freq: u32,
+    pub bw: Bandwidth,
+    pub sf: SpreadingFactor,
+    pub cr: CodingRate,
+    /// Preamble length in symbols.
+    pub preamble_len: u16,
+    /// Only frames sent with the same sync word are received.
+    pub sync_word: u8,
+    /// Output power in dBm.
+    pub power: u8,
+}
+
+impl RadioSettings {
+    /// Check the settings are legal and usable by the modem.
+    pub fn validate(&self) -> Result<(), SettingsError> {
+        let half = crate::airtime::bandwidth_hz(self.bw) / 2;
+        if self.freq.saturating_sub(half) < BAND_MIN_HZ
+            || self.freq.saturating_add(half) > BAND_MAX_HZ
+        {
+            return Err(SettingsError::Frequency);
+        }
+        if !SPREADING_FACTORS.contains(&self.sf) {
+            return Err(SettingsError::SpreadingFactor);
+        }
+        if self.preamble_len < MIN_PREAMBLE {
+            return Err(SettingsError::Preamble);
+        }
+        if !(MIN_POWER_DBM..=MAX_POWER_DBM).contains(&self.power) {
+            return Err(SettingsError::Power);
+        }
+        Ok(())
+    }
+
+    pub fn channel(&self) -> LoRaChannel {
+        LoRaChannel {
+            freq: self.freq,
+            bw: self.bw,
+            sf: self.sf,
+            cr: self.cr,
+        }
+    }
+
+    pub fn lora(&self) -> LoRaConfig {
+        LoRaConfig {
+            preamble_len: self.preamble_len,
+            ..CONFIG_LORA
+        }
+    }
+
+    pub fn pa(&self) -> PaConfig {
+        PaConfig {
+            power: self.power as _,
+            ..CONFIG_PA
+        }
+    }
+
+    /// Driver configuration, everything but the sync word.
+    pub fn config(&self) -> device::Config {
+        device::Config {
+            modem: Modem::LoRa(self.lora()),
+            channel: Channel::LoRa(self.channel()),
+            pa_config: self.pa(),
+            ..CONFIG_RADIO
+        }
+    }
+
+    /// Write the settings to `out`, returning their length.
+    pub fn encode(&self, out: &mut [u8]) -> usize {
+        if out.len() < ENCODED_LEN {
+            return 0;
+        }
+        out[0..4].copy_from_slice(&self.freq.to_le_bytes());
+        out[4] = index(&BANDWIDTHS, &self.bw);
+        out[5] = index(&SPREADING_FACTORS, &self.sf);
+        out[6] = index(&CODING_RATES, &self.cr);
+        out[7..9].copy_from_slice(&self.preamble_len.to_le_bytes());
+        out[9] = self.sync_word;
+        out[10] = self.power;
+        out[11] = 0;
+        ENCODED_LEN
+    }
+
+    /// Read back settings written by [`RadioSettings::encode`], validated.
+    pub fn decode(bytes: &[u8]) -> Result<Self, SettingsError> {
+        if bytes.len() < ENCODED_LEN {
+            return Err(SettingsError::Corrupted);
+        }
+        let settings = RadioSettings {
+            freq: u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            bw: pick(&BANDWIDTHS, bytes[4])?,
+            sf: pick(&SPREADING_FACTORS, bytes[5])?,
+            cr: pick(&CODING_RATES, bytes[6])?,
+            preamble_len: u16::from_le_bytes([bytes[7], bytes[8]]),
+            sync_word: bytes[9],
+            power: bytes[10],
+        };
+        settings.validate()?;
+        Ok(settings)
+    }
+}
+
+impl Default for RadioSettings {
+    fn default() -> Self {
+        Self {
+            freq: CONFIG_CH.freq,
+            bw: CONFIG_CH.bw,
+            sf: CONFIG_CH.sf,
+            cr: CONFIG_CH.cr,
+            preamble_len: CONFIG_LORA.preamble_len,
+            sync_word: DEFAULT_SYNC_WORD,
+            power: CONFIG_PA.power as u8,
+        }
+    }
+}
+
+/// Settings of `node` and of the display, saved as a whole.
+pub fn save<F: Flash>(store: &mut Store<F>, node: &Node, signal: bool) -> Result<(), StoreError> {
+    let mut radio = [0u8; ENCODED_LEN];
+    node.radio().encode(&mut radio);
+    store.set(KEY_RADIO, &radio)?;
+    store.set(KEY_NAME, node.presence.name.as_bytes())?;
+    let key = node.key.as_ref().map_or(&[][..], |key| &key[..]);
+    store.set(KEY_CHANNEL_KEY, key)?;
+    store.set(KEY_SIGNAL, &[signal as u8])
+}
+
+/// Restore the settings saved with [`save`] into `node`, returning the
+/// display's. Settings never saved or unreadable are left as they are.
+pub fn load<F: Flash>(store: &mut Store<F>, node: &mut Node) -> Option<bool> {
+    let mut value = [0u8; KEY_LEN];
+    if let Some(len) = store.get(KEY_RADIO, &mut value) {
+        if let Ok(radio) = RadioSettings::decode(&value[..len]) {
+            _ = node.set_radio(radio);
+        }
+    }
+    if let Some(len) = store.get(KEY_NAME, &mut value) {
+        if let Ok(name) = core::str::from_utf8(&value[..len]) {
+            node.set_name(name);
+        }
+    }
+    match store.get(KEY_CHANNEL_KEY, &mut value) {
+        Some(0) => node.key = None,
+        Some(KEY_LEN) => node.key = Some(value),
+        _ => {}
+    }
+    store.get(KEY_SIGNAL, &mut value).map(|_| value[0] != 0)
+}
+
+/// Why no epoch could be reserved, sealed frames being refused until one is.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum EpochError {
+    Store(StoreError),
+    /// The last epoch reserved cannot be read back, starting over could
+    /// reuse nonces.
+    Lost,
+    /// Every epoch was used with the channel key.
+    Exhausted,
+}
+
+impl EpochError {
+    /// What the user is told, only the first one goes away by itself.
+    pub fn text(self) -> &'static str {
+        match self {
+            EpochError::Store(_) => "Epoch not saved, not sealing",
+            EpochError::Lost => "Epoch lost, not sealing",
+            EpochError::Exhausted => "Epochs used up, not sealing",
+        }
+    }
+}
+
+/// Reserve a fresh epoch at boot or once [`Node::epoch_spent`], one past the
+/// last one reserved. It is saved before being returned so that nonces are
+/// never reused after a reset.
+///
+/// The count goes on across key changes, so a key entered again never meets
+/// the nonces it sealed before. Only a store holding nothing yet starts from
+/// 0, one that lost the epoch or went through all of them is refused for
+/// good.
+pub fn reserve_epoch<F: Flash>(store: &mut Store<F>) -> Result<u16, EpochError> {
+    let mut value = [0u8; 4];
+    let epoch = match store.get(KEY_EPOCH, &mut value) {
+        Some(2) => u16::from_le_bytes([value[0], value[1]])
+            .checked_add(1)
+            .ok_or(EpochError::Exhausted)?,
+        None if store.is_empty() => 0,
+        _ => return Err(EpochError::Lost),
+    };
+    save_epoch(store, epoch).map_err(EpochError::Store)?;
+    Ok(epoch)
+}
+
+/// Record `epoch` as the last one reserved.
+pub fn save_epoch<F: Flash>(store: &mut Store<F>, epoch: u16) -> Result<(), StoreError> {
+    store.set(KEY_EPOCH, &epoch.to_le_bytes())
+}
+
+/// Give `node` the epoch [`reserve_epoch`] finds. When there is none and
+/// `node` has a key, `disp` is told why its frames cannot be sent.
+pub fn renew_epoch<F: Flash>(
+    store: &mut Store<F>,
+    node: &mut Node,
+    disp: &mut impl Interface,
+) -> Option<u16> {
+    let epoch = reserve_epoch(store);
+    if let (Err(e), Some(_)) = (epoch, &node.key) {
+        info!("no epoch to seal with: {}", e);
+        disp.add_log(None, e.text().as_bytes(), None, None);
+    }
+    node.set_epoch(epoch.ok());
+    epoch.ok()
+}
+
+fn pick<T: Copy>(values: &[T], index: u8) -> Result<T, SettingsError> {
+    values
+        .get(index as usize)
+        .copied()
+        .ok_or(SettingsError::Corrupted)
+}
+
+fn index<T: PartialEq>(values: &[T], value: &T) -> u8 {
+    values.iter().position(|v| v == value).unwrap_or(0) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::RamFlash;
+
+    type Ram = RamFlash<{ 2 * 4096 }>;
+
+    #[test]
+    fn epochs_count_up_from_an_empty_store() {
+        let mut store = Store::mount(Ram::new());
+        assert_eq!(reserve_epoch(&mut store), Ok(0));
+        assert_eq!(reserve_epoch(&mut store), Ok(1));
+        let mut store = Store::mount(Ram {
+            data: store.flash().data,
+            ..Ram::new()
+        });
+        assert_eq!(reserve_epoch(&mut store), Ok(2));
+    }
+
+    #[test]
+    fn lost_or_used_epochs_are_refused() {
+        let mut store = Store::mount(Ram::new());
+        store.set(KEY_SIGNAL, &[1]).unwrap();
+        assert_eq!(reserve_epoch(&mut store), Err(EpochError::Lost));
+        store.set(KEY_EPOCH, &[1]).unwrap();
+        assert_eq!(reserve_epoch(&mut store), Err(EpochError::Lost));
+        save_epoch(&mut store, u16::MAX - 1).unwrap();
+        assert_eq!(reserve_epoch(&mut store), Ok(u16::MAX));
+        assert_eq!(reserve_epoch(&mut store), Err(EpochError::Exhausted));
+    }
+
+    #[test]
+    fn key_changes_keep_counting() {
+        let mut store = Store::mount(Ram::new());
+        assert_eq!(reserve_epoch(&mut store), Ok(0));
+        let mut node = Node::new(1);
+        node.key = Some([1; KEY_LEN]);
+        save(&mut store, &node, true).unwrap();
+        node.key = Some([2; KEY_LEN]);
+        save(&mut store, &node, true).unwrap();
+        assert_eq!(reserve_epoch(&mut store), Ok(1));
+        // back to the first key, its epoch 0 is not used again
+        node.key = Some([1; KEY_LEN]);
+        save(&mut store, &node, true).unwrap();
+        assert_eq!(reserve_epoch(&mut store), Ok(2));
+
+        save_epoch(&mut store, u16::MAX).unwrap();
+        node.key = Some([3; KEY_LEN]);
+        save(&mut store, &node, true).unwrap();
+        assert_eq!(reserve_epoch(&mut store), Err(EpochError::Exhausted));
+    }
+}
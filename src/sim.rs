@@ -0,0 +1,709 @@
+//! In-memory radio medium for running several nodes on the host.
+//!
+//! An [`Air`] is shared by any number of [`SimRadio`]s. Time only moves when
+//! [`Air::advance`] is called, which makes packet loss, latency, corruption
+//! and collisions reproducible from the configured seed. Every node hears
+//! every other one unless [`Air::set_link`] cuts them apart, which lets a
+//! chain of nodes exercise relaying. A packet is only received by radios
+//! listening for its whole time on air, and [`Air::inject`] makes a radio
+//! fail like a misbehaving chip would.
+//!
+//! ```
+//! # use lora_rust::sim::{Air, AirConfig, SimError};
+//! # use radio::{Receive, Transmit};
+//! # fn main() -> Result<(), SimError> {
+//! let air = Air::new(AirConfig::default());
+//! let mut alice = air.radio();
+//! let mut bob = air.radio();
+//! bob.start_receive()?;
+//! alice.start_transmit(b"hello")?;
+//! air.advance(100);
+//! assert!(bob.check_receive(false)?);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`SimNode`] runs the whole state machine of a node on a radio, [`run`]
+//! moving a few of them and the air forward together.
+#![allow(dead_code)]
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::vec::Vec;
+
+use radio::{Receive, ReceiveInfo, Transmit};
+
+use crate::frame::{Address, BROADCAST};
+use crate::interface::{Delivery, Interface};
+use crate::node::Node;
+use crate::settings::RadioSettings;
+use crate::state::{
+    ChannelActivity, Outbox, Outgoing, Packet, RadioError, Recovery, ResetRadio, SignalInfo, State,
+};
+
+#[derive(Clone, Copy)]
+pub struct AirConfig {
+    /// Chance in percent that a receiver misses a packet entirely.
+    pub loss: u8,
+    /// Chance in percent that a receiver gets a packet failing its CRC.
+    pub corruption: u8,
+    /// Time a packet occupies the air, overlapping packets collide.
+    pub airtime_ms: u32,
+    /// Delay between the end of a transmission and its reception.
+    pub latency_ms: u32,
+    pub rssi: i16,
+    pub snr: i16,
+    pub seed: u32,
+}
+
+impl Default for AirConfig {
+    fn default() -> Self {
+        Self {
+            loss: 0,
+            corruption: 0,
+            airtime_ms: 50,
+            latency_ms: 0,
+            rssi: -60,
+            snr: 9,
+            seed: 0x1234_5678,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SimError {
+    /// A packet arrived but failed its CRC, as `sx127xError::Crc` would.
+    Crc,
+    /// The receive buffer is smaller than the packet.
+    BufferSize,
+    /// A transmission is already in progress.
+    Busy,
+    /// The chip stopped answering, only injected.
+    Timeout,
+    /// The chip answered garbage, only injected.
+    InvalidResponse,
+}
+
+impl RadioError for SimError {
+    fn recovery(&self) -> Recovery {
+        match self {
+            SimError::Crc | SimError::BufferSize | SimError::Busy => Recovery::Idle,
+            SimError::Timeout | SimError::InvalidResponse => Recovery::Reset,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct SimInfo {
+    pub rssi: i16,
+    pub snr: Option<i16>,
+}
+
+impl ReceiveInfo for SimInfo {
+    fn rssi(&self) -> i16 {
+        self.rssi
+    }
+}
+
+impl SignalInfo for SimInfo {
+    fn rssi(&self) -> i16 {
+        self.rssi
+    }
+    fn snr(&self) -> Option<i16> {
+        self.snr
+    }
+}
+
+struct Transmission {
+    from: usize,
+    start: u32,
+    end: u32,
+    data: Vec<u8>,
+    delivered: bool,
+}
+
+enum Reception {
+    Packet(Vec<u8>, SimInfo),
+    Corrupted,
+}
+
+#[derive(Default)]
+struct Slot {
+    /// Time the receiver was started, `None` while it is not receiving.
+    listening: Option<u32>,
+    sending_until: Option<u32>,
+    received: Option<Reception>,
+    /// Error the next radio operations fail with, and how many of them.
+    fault: Option<(SimError, u32)>,
+}
+
+impl Slot {
+    fn listen(&mut self, now: u32) {
+        if self.listening.is_none() {
+            self.listening = Some(now);
+        }
+    }
+
+    /// Fail with the injected error while some is left.
+    fn check(&mut self) -> Result<(), SimError> {
+        match &mut self.fault {
+            Some((e, left)) if *left > 0 => {
+                *left -= 1;
+                Err(*e)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+struct Inner {
+    config: AirConfig,
+    now: u32,
+    rng: u32,
+    slots: Vec<Slot>,
+    /// Pairs of nodes out of range of each other, lowest id first.
+    cut: Vec<(usize, usize)>,
+    transmissions: Vec<Transmission>,
+    /// Packets put on the air, delivered or not.
+    sent: usize,
+    /// Receptions lost to overlapping transmissions.
+    collisions: usize,
+}
+
+impl Inner {
+    fn roll(&mut self, percent: u8) -> bool {
+        // xorshift32
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        (self.rng % 100) < percent as u32
+    }
+
+    fn in_range(&self, a: usize, b: usize) -> bool {
+        !self.cut.contains(&(a.min(b), a.max(b)))
+    }
+
+    fn deliver(&mut self) {
+        let now = self.now;
+        let latency = self.config.latency_ms;
+        for t in 0..self.transmissions.len() {
+            let (from, start, end) = {
+                let tx = &self.transmissions[t];
+                if tx.delivered || tx.end + latency > now {
+                    continue;
+                }
+                (tx.from, tx.start, tx.end)
+            };
+            for to in 0..self.slots.len() {
+                // a receiver started mid-packet missed its preamble
+                let listening = matches!(self.slots[to].listening, Some(since) if since <= start);
+                if to == from || !listening || !self.in_range(from, to) {
+                    continue;
+                }
+                // only transmissions the receiver can hear interfere
+                let collided = self.transmissions.iter().any(|o| {
+                    o.from != from
+                        && o.from != to
+                        && self.in_range(o.from, to)
+                        && o.start < end
+                        && start < o.end
+                });
+                if collided {
+                    self.collisions += 1;
+                    continue;
+                }
+                if self.roll(self.config.loss) {
+                    continue;
+                }
+                let reception = if self.roll(self.config.corruption) {
+                    Reception::Corrupted
+                } else {
+                    Reception::Packet(
+                        self.transmissions[t].data.clone(),
+                        SimInfo {
+                            rssi: self.config.rssi,
+                            snr: Some(self.config.snr),
+                        },
+                    )
+                };
+                self.slots[to].received = Some(reception);
+            }
+            self.transmissions[t].delivered = true;
+        }
+        // keep finished transmissions around long enough to detect collisions
+        let horizon = now.saturating_sub(self.config.airtime_ms + latency);
+        self.transmissions
+            .retain(|tx| !tx.delivered || tx.end >= horizon);
+    }
+}
+
+/// Shared medium, cloning it gives another handle on the same air.
+#[derive(Clone)]
+pub struct Air {
+    inner: Rc<RefCell<Inner>>,
+}
+
+impl Air {
+    pub fn new(config: AirConfig) -> Self {
+        Self {
+            inner: Rc::new(RefCell::new(Inner {
+                config,
+                now: 0,
+                rng: config.seed.max(1),
+                slots: Vec::new(),
+                cut: Vec::new(),
+                transmissions: Vec::new(),
+                sent: 0,
+                collisions: 0,
+            })),
+        }
+    }
+
+    /// Attach a new node to the air.
+    pub fn radio(&self) -> SimRadio {
+        let mut inner = self.inner.borrow_mut();
+        inner.slots.push(Slot::default());
+        SimRadio {
+            air: self.clone(),
+            id: inner.slots.len() - 1,
+        }
+    }
+
+    /// Current time of the medium in milliseconds.
+    pub fn now(&self) -> u32 {
+        self.inner.borrow().now
+    }
+
+    /// Move time forward, delivering every packet that finished in between.
+    pub fn advance(&self, ms: u32) {
+        let mut inner = self.inner.borrow_mut();
+        for _ in 0..ms {
+            inner.now += 1;
+            inner.deliver();
+        }
+    }
+
+    /// Put the nodes `a` and `b`, as numbered by [`SimRadio::id`], in or
+    /// out of range of each other.
+    pub fn set_link(&self, a: usize, b: usize, in_range: bool) {
+        let mut inner = self.inner.borrow_mut();
+        let pair = (a.min(b), a.max(b));
+        inner.cut.retain(|&cut| cut != pair);
+        if !in_range {
+            inner.cut.push(pair);
+        }
+    }
+
+    /// Make the next `count` operations of the radio `id` fail with `error`,
+    /// resets included.
+    pub fn inject(&self, id: usize, error: SimError, count: u32) {
+        self.inner.borrow_mut().slots[id].fault = Some((error, count));
+    }
+
+    pub fn set_config(&self, config: AirConfig) {
+        self.inner.borrow_mut().config = config;
+    }
+
+    pub fn sent(&self) -> usize {
+        self.inner.borrow().sent
+    }
+
+    pub fn collisions(&self) -> usize {
+        self.inner.borrow().collisions
+    }
+
+    /// `true` while any node is transmitting.
+    pub fn busy(&self) -> bool {
+        let inner = self.inner.borrow();
+        inner
+            .transmissions
+            .iter()
+            .any(|tx| tx.start <= inner.now && inner.now < tx.end)
+    }
+}
+
+/// One node's view of the [`Air`].
+pub struct SimRadio {
+    air: Air,
+    id: usize,
+}
+
+impl SimRadio {
+    pub fn id(&self) -> usize {
+        self.id
+    }
+}
+
+impl Transmit for SimRadio {
+    type Error = SimError;
+
+    fn start_transmit(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        let mut inner = self.air.inner.borrow_mut();
+        let now = inner.now;
+        inner.slots[self.id].check()?;
+        if let Some(until) = inner.slots[self.id].sending_until {
+            if until > now {
+                return Err(SimError::Busy);
+            }
+        }
+        let end = now + inner.config.airtime_ms;
+        inner.slots[self.id].listening = None;
+        inner.slots[self.id].sending_until = Some(end);
+        inner.transmissions.push(Transmission {
+            from: self.id,
+            start: now,
+            end,
+            data: data.to_vec(),
+            delivered: false,
+        });
+        inner.sent += 1;
+        Ok(())
+    }
+
+    fn check_transmit(&mut self) -> Result<bool, Self::Error> {
+        let mut inner = self.air.inner.borrow_mut();
+        let now = inner.now;
+        let slot = &mut inner.slots[self.id];
+        slot.check()?;
+        match slot.sending_until {
+            Some(until) if until > now => Ok(false),
+            _ => {
+                slot.sending_until = None;
+                Ok(true)
+            }
+        }
+    }
+}
+
+impl Receive for SimRadio {
+    type Info = SimInfo;
+    type Error = SimError;
+
+    fn start_receive(&mut self) -> Result<(), Self::Error> {
+        let mut inner = self.air.inner.borrow_mut();
+        let now = inner.now;
+        let slot = &mut inner.slots[self.id];
+        slot.check()?;
+        slot.listen(now);
+        slot.received = None;
+        Ok(())
+    }
+
+    fn check_receive(&mut self, restart: bool) -> Result<bool, Self::Error> {
+        let mut inner = self.air.inner.borrow_mut();
+        let now = inner.now;
+        let slot = &mut inner.slots[self.id];
+        slot.check()?;
+        match slot.received {
+            Some(Reception::Packet(..)) => Ok(true),
+            Some(Reception::Corrupted) => {
+                slot.received = None;
+                slot.listening = None;
+                if restart {
+                    slot.listen(now);
+                }
+                Err(SimError::Crc)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn get_received(&mut self, buff: &mut [u8]) -> Result<(usize, Self::Info), Self::Error> {
+        let mut inner = self.air.inner.borrow_mut();
+        let slot = &mut inner.slots[self.id];
+        slot.check()?;
+        match slot.received.take() {
+            Some(Reception::Packet(data, info)) => {
+                if data.len() > buff.len() {
+                    return Err(SimError::BufferSize);
+                }
+                buff[..data.len()].copy_from_slice(&data);
+                slot.listening = None;
+                Ok((data.len(), info))
+            }
+            _ => Ok((0, SimInfo::default())),
+        }
+    }
+}
+
+impl ResetRadio for SimRadio {
+    type Error = SimError;
+
+    fn reset(&mut self, _settings: &RadioSettings) -> Result<(), Self::Error> {
+        let mut inner = self.air.inner.borrow_mut();
+        let slot = &mut inner.slots[self.id];
+        slot.check()?;
+        *slot = Slot {
+            fault: slot.fault,
+            ..Slot::default()
+        };
+        Ok(())
+    }
+}
+
+impl ChannelActivity for SimRadio {
+    type Error = SimError;
+
+    fn channel_busy(&mut self) -> Result<bool, Self::Error> {
+        let mut inner = self.air.inner.borrow_mut();
+        inner.slots[self.id].check()?;
+        let now = inner.now;
+        Ok(inner.transmissions.iter().any(|tx| {
+            tx.from != self.id
+                && inner.in_range(tx.from, self.id)
+                && tx.start <= now
+                && now < tx.end
+        }))
+    }
+}
+
+/// What the screen of a node showed, to check it in tests.
+#[derive(Default)]
+pub struct Transcript {
+    /// Messages received, as sender name and body.
+    pub received: Vec<(Vec<u8>, Vec<u8>)>,
+    /// Lines of the node itself, like radio failures.
+    pub notices: Vec<Vec<u8>>,
+    /// Our messages and their latest delivery.
+    pub own: Vec<(u16, Delivery)>,
+}
+
+impl Transcript {
+    /// Delivery of our message `id`.
+    pub fn delivery(&self, id: u16) -> Option<Delivery> {
+        self.own
+            .iter()
+            .find(|(own, _)| *own == id)
+            .map(|&(_, status)| status)
+    }
+}
+
+impl Interface for Transcript {
+    fn set_title(&mut self, _title: &[u8]) {}
+    fn set_input(&mut self, _input: &[u8], _cursor: usize) {}
+    fn set_overlay(&mut self, _overlay: Option<&'static str>) {}
+    fn add_log(&mut self, from: Option<&[u8]>, body: &[u8], _snr: Option<i16>, _rssi: Option<i16>) {
+        match from {
+            Some(from) => self.received.push((from.to_vec(), body.to_vec())),
+            None => self.notices.push(body.to_vec()),
+        }
+    }
+    fn add_own(&mut self, id: u16, _to: Option<&[u8]>, _body: &[u8], status: Delivery) {
+        self.own.push((id, status));
+    }
+    fn set_delivery(&mut self, id: u16, status: Delivery) {
+        for own in self.own.iter_mut().filter(|(own, _)| *own == id) {
+            own.1 = status;
+        }
+    }
+}
+
+/// A node running the state machine on its own radio.
+pub struct SimNode {
+    pub radio: SimRadio,
+    pub node: Node,
+    pub state: State,
+    pub outbox: Outbox<4>,
+    pub screen: Transcript,
+}
+
+impl SimNode {
+    /// A node booted for the first time, in epoch 0.
+    pub fn new(air: &Air, address: Address) -> Self {
+        let mut node = Node::new(address);
+        node.set_epoch(Some(0));
+        Self {
+            radio: air.radio(),
+            node,
+            state: State::Init,
+            outbox: Outbox::new(),
+            screen: Transcript::default(),
+        }
+    }
+
+    /// Queue a chat message for the whole channel, `false` when the outbox
+    /// is full.
+    pub fn send(&mut self, text: &str) -> bool {
+        self.send_to(BROADCAST, text)
+    }
+
+    /// Queue a chat message for `to` alone, `false` when the outbox is full.
+    pub fn send_to(&mut self, to: Address, text: &str) -> bool {
+        match Packet::from_slice(text.as_bytes()) {
+            Ok(body) => self.outbox.push_back(Outgoing { to, body }).is_ok(),
+            Err(_) => false,
+        }
+    }
+
+    /// Run one step of the state machine at the time of the air.
+    pub fn step(&mut self) {
+        let now = self.radio.air.now();
+        let state = self.state;
+        self.state = match state.run_state(
+            &mut self.radio,
+            &mut self.node,
+            &mut self.outbox,
+            &mut self.screen,
+            now,
+        ) {
+            Ok(next) => next,
+            Err(e) => state.on_error(e, &mut self.node, now),
+        };
+    }
+}
+
+/// Step every node then advance the air, a millisecond at a time for `ms`.
+pub fn run(air: &Air, nodes: &mut [SimNode], ms: u32) {
+    for _ in 0..ms {
+        for node in nodes.iter_mut() {
+            node.step();
+        }
+        air.advance(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::Key;
+
+    /// A node that only sends what it is asked to.
+    fn quiet(air: &Air, address: Address) -> SimNode {
+        let mut node = SimNode::new(air, address);
+        node.node.beacon_interval_ms = 0;
+        node.node.routes.advertise_at = 1_000_000;
+        node
+    }
+
+    fn bodies(node: &SimNode) -> Vec<&[u8]> {
+        node.screen
+            .received
+            .iter()
+            .map(|(_, body)| &body[..])
+            .collect()
+    }
+
+    #[test]
+    fn chat_is_received_and_acknowledged() {
+        let air = Air::new(AirConfig::default());
+        let mut nodes = [quiet(&air, 1), quiet(&air, 2)];
+        assert!(nodes[0].send("hello"));
+        run(&air, &mut nodes, 2_000);
+        let [alice, bob] = &nodes;
+        assert_eq!(bob.screen.received, [(b"0001".to_vec(), b"hello".to_vec())]);
+        assert_eq!(alice.screen.own, [(1, Delivery::Delivered)]);
+        assert!(alice.node.pending.is_empty());
+    }
+
+    #[test]
+    fn sealed_chat_needs_the_channel_key() {
+        const KEY: Key = [7; 32];
+        let air = Air::new(AirConfig::default());
+        let mut nodes = [quiet(&air, 1), quiet(&air, 2), quiet(&air, 3)];
+        nodes[0].node.key = Some(KEY);
+        nodes[1].node.key = Some(KEY);
+        nodes[2].node.key = Some([8; 32]);
+        nodes[0].send("secret");
+        run(&air, &mut nodes, 2_000);
+        assert_eq!(bodies(&nodes[1]), [b"secret"]);
+        assert!(nodes[2].screen.received.is_empty());
+        assert!(nodes[2].node.rejected > 0);
+    }
+
+    #[test]
+    fn lost_message_is_sent_again_and_shown_once() {
+        let air = Air::new(AirConfig {
+            loss: 100,
+            ..AirConfig::default()
+        });
+        let mut nodes = [quiet(&air, 1), quiet(&air, 2)];
+        nodes[0].send("again");
+        run(&air, &mut nodes, 1_000);
+        assert!(nodes[1].screen.received.is_empty());
+        air.set_config(AirConfig::default());
+        run(&air, &mut nodes, 8_000);
+        assert_eq!(bodies(&nodes[1]), [b"again"]);
+        assert_eq!(nodes[0].screen.own, [(1, Delivery::Delivered)]);
+        assert!(air.sent() >= 3);
+    }
+
+    #[test]
+    fn corrupted_packets_are_dropped() {
+        let air = Air::new(AirConfig {
+            corruption: 100,
+            ..AirConfig::default()
+        });
+        let mut nodes = [quiet(&air, 1), quiet(&air, 2)];
+        nodes[0].send("noise");
+        run(&air, &mut nodes, 2_000);
+        assert!(nodes[1].screen.received.is_empty());
+        assert!(nodes[1].state == State::Idle);
+        assert_eq!(nodes[0].screen.own, [(1, Delivery::Pending)]);
+    }
+
+    #[test]
+    fn injected_timeouts_reset_the_radio() {
+        let air = Air::new(AirConfig::default());
+        let mut nodes = [quiet(&air, 1), quiet(&air, 2)];
+        air.inject(nodes[0].radio.id(), SimError::Timeout, 3);
+        nodes[0].send("hello");
+        run(&air, &mut nodes, 3_000);
+        let [alice, bob] = &nodes;
+        assert_eq!(alice.screen.notices, [b"Radio lost, resetting"]);
+        assert_eq!(bodies(bob), [b"hello"]);
+        assert_eq!(alice.node.resets, 0);
+    }
+
+    #[test]
+    fn radio_failing_for_good_ends_failed() {
+        let air = Air::new(AirConfig::default());
+        let mut nodes = [quiet(&air, 1)];
+        air.inject(nodes[0].radio.id(), SimError::InvalidResponse, u32::MAX);
+        run(&air, &mut nodes, 5_000);
+        assert!(nodes[0].state == State::Failed);
+        assert_eq!(
+            nodes[0].screen.notices,
+            [&b"Radio lost, resetting"[..], b"Radio failed, power cycle"]
+        );
+    }
+
+    #[test]
+    fn receiver_started_mid_packet_misses_it() {
+        let air = Air::new(AirConfig::default());
+        let mut alice = air.radio();
+        let mut bob = air.radio();
+        alice.start_transmit(b"late").unwrap();
+        air.advance(10);
+        bob.start_receive().unwrap();
+        air.advance(100);
+        assert!(!bob.check_receive(false).unwrap());
+        // listening again does not restart an ongoing reception
+        alice.start_transmit(b"early").unwrap();
+        air.advance(10);
+        bob.start_receive().unwrap();
+        air.advance(100);
+        assert!(bob.check_receive(false).unwrap());
+    }
+
+    #[test]
+    fn overlapping_packets_collide() {
+        let air = Air::new(AirConfig::default());
+        let mut alice = air.radio();
+        let mut bob = air.radio();
+        let mut carol = air.radio();
+        bob.start_receive().unwrap();
+        alice.start_transmit(b"one").unwrap();
+        air.advance(20);
+        carol.start_transmit(b"two").unwrap();
+        air.advance(100);
+        assert!(!bob.check_receive(false).unwrap());
+        assert_eq!(air.collisions(), 2);
+        // out of range of each other, carol does not jam alice at bob's
+        air.set_link(carol.id(), bob.id(), false);
+        alice.start_transmit(b"three").unwrap();
+        air.advance(20);
+        carol.start_transmit(b"four").unwrap();
+        air.advance(100);
+        assert!(bob.check_receive(false).unwrap());
+    }
+}
@@ -0,0 +1,927 @@
+#![allow(dead_code)]
+
+use heapless::{Deque, String, Vec};
+use radio::{Receive, Transmit};
+
+use crate::airtime::{message_ms, time_on_air_ms};
+use crate::crypto::{self, CryptoError};
+use crate::fragment::{self, MAX_SINGLE};
+use crate::frame::{
+    self, address_name, parse_address, Address, FrameError, Header, Kind, BROADCAST, DEFAULT_TTL,
+    FLAG_ACK, FLAG_ENCRYPTED, FLAG_FRAGMENT, MAX_PAYLOAD,
+};
+use crate::interface::{Delivery, Interface};
+use crate::mesh::{RELAY_DELAY_MS, RELAY_JITTER_MS};
+use crate::neighbours::{Presence, NAME_LEN};
+use crate::node::{Burst, Node, Pending, ACK_TIMEOUT_MS, MAX_RETRIES};
+use crate::replay;
+use crate::routes::ADVERTISE_MS;
+use crate::settings::RadioSettings;
+
+/// Largest LoRa packet.
+pub const MAX_PACKET: usize = 255;
+
+/// Longest message, split in fragments when longer than one frame.
+pub const MAX_MESSAGE: usize = 1024;
+
+/// Body of a chat message waiting to be framed and sent.
+pub type Packet = Vec<u8, MAX_MESSAGE>;
+pub type Outbox<const N: usize> = Deque<Outgoing, N>;
+
+/// A chat message waiting in the [`Outbox`].
+#[derive(Clone)]
+pub struct Outgoing {
+    /// Recipient, [`BROADCAST`] for the whole channel.
+    pub to: Address,
+    pub body: Packet,
+}
+
+impl Outgoing {
+    /// Message for the whole channel.
+    pub fn broadcast(body: Packet) -> Self {
+        Self {
+            to: BROADCAST,
+            body,
+        }
+    }
+
+    /// Message typed by the user, `@name text` sending `text` to the
+    /// neighbour announcing `name` or to the node of hex address `name`.
+    /// `None` when the recipient is unknown or the text too long.
+    pub fn from_input(node: &Node, input: &[u8]) -> Option<Self> {
+        let (to, text) = match input.strip_prefix(b"@") {
+            Some(addressed) => {
+                let end = addressed
+                    .iter()
+                    .position(|&c| c == b' ')
+                    .unwrap_or(addressed.len());
+                let name = core::str::from_utf8(&addressed[..end]).ok()?;
+                let to = node.neighbours.address(name).or(parse_address(name))?;
+                (to, addressed.get(end + 1..).unwrap_or(&[]))
+            }
+            None => (BROADCAST, input),
+        };
+        Some(Self {
+            to,
+            body: Packet::from_slice(text).ok()?,
+        })
+    }
+}
+
+/// Number of radio resets tried before the state machine gives up.
+pub const RESET_ATTEMPTS: u8 = 5;
+/// Wait before the first reset, doubled on every retry.
+const RESET_BACKOFF_MS: u32 = 50;
+
+/// What the state machine should do after a radio error.
+pub enum Recovery {
+    /// Drop the current operation and go back to listening.
+    Idle,
+    /// The chip is in an unknown state, reset and reconfigure it.
+    Reset,
+    /// Nothing sensible can be done.
+    Fatal(&'static str),
+}
+
+pub trait RadioError {
+    fn recovery(&self) -> Recovery;
+}
+
+/// Bring a radio to a known state, toggling its reset line, and configure
+/// it with `settings`.
+pub trait ResetRadio {
+    type Error;
+    fn reset(&mut self, settings: &RadioSettings) -> Result<(), Self::Error>;
+}
+
+/// Listen before talk, see [`crate::lbt`].
+pub trait ChannelActivity {
+    type Error;
+    /// `true` when someone is transmitting, the radio being in receive mode.
+    fn channel_busy(&mut self) -> Result<bool, Self::Error>;
+}
+
+/// Signal quality of a received packet.
+pub trait SignalInfo {
+    fn rssi(&self) -> i16;
+    fn snr(&self) -> Option<i16>;
+}
+
+/// Why a frame could not be put together, it is not sent.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SealError {
+    /// No epoch is reserved, sealing could reuse a nonce.
+    Epoch,
+    Crypto(CryptoError),
+    Frame(FrameError),
+}
+
+/// `true` once `now` is at or past `deadline`, both in wrapping milliseconds.
+pub fn reached(now: u32, deadline: u32) -> bool {
+    now.wrapping_sub(deadline) < u32::MAX / 2
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum State {
+    Init,
+    PrepareIdle,
+    Reset {
+        attempt: u8,
+        at: u32,
+    },
+    /// Apply new radio settings.
+    Configure,
+    Failed,
+    Idle,
+    Sending,
+    SendingDone,
+    Received,
+}
+
+impl State {
+    pub fn run_state<R, E, const N: usize>(
+        &self,
+        radio: &mut R,
+        node: &mut Node,
+        outbox: &mut Outbox<N>,
+        disp: &mut impl Interface,
+        now: u32,
+    ) -> Result<Self, E>
+    where
+        R: Transmit<Error = E>
+            + Receive<Error = E>
+            + ResetRadio<Error = E>
+            + ChannelActivity<Error = E>,
+        <R as Receive>::Info: SignalInfo,
+    {
+        match self {
+            State::Init => {
+                info!("init");
+                Ok(State::PrepareIdle)
+            }
+            State::Reset { attempt, .. } if *attempt >= RESET_ATTEMPTS => {
+                info!("radio unrecoverable after {} resets", attempt);
+                disp.add_log(None, b"Radio failed, power cycle", None, None);
+                Ok(State::Failed)
+            }
+            State::Reset { attempt, at } => {
+                if !reached(now, *at) {
+                    return Ok(*self);
+                }
+                info!("resetting radio, attempt {}", attempt);
+                if *attempt == 0 {
+                    disp.add_log(None, b"Radio lost, resetting", None, None);
+                }
+                radio.reset(node.radio())?;
+                Ok(State::PrepareIdle)
+            }
+            State::Configure => {
+                info!("applying radio settings");
+                node.reconfigure = false;
+                radio.reset(node.radio())?;
+                Ok(State::PrepareIdle)
+            }
+            State::Failed => Ok(State::Failed),
+            State::PrepareIdle => {
+                radio.start_receive()?;
+
+                Ok(State::Idle)
+            }
+            State::Idle => {
+                if node.reconfigure {
+                    return Ok(State::Configure);
+                }
+                if node.outgoing.is_none() {
+                    stage(node, outbox, disp, now);
+                }
+                let airtime = node.outgoing.as_ref().map(|frame| {
+                    time_on_air_ms(&node.radio().channel(), &node.radio().lora(), frame.len())
+                });
+                if airtime.map_or(false, |airtime| node.duty.too_long(airtime)) {
+                    info!("frame longer than the whole duty cycle budget, dropping");
+                    node.outgoing = None;
+                }
+                // the frame waits while the hourly budget is spent
+                let allowed = airtime.map_or(false, |airtime| node.duty.allows(airtime, now));
+                if allowed && node.backoff.ready(now) {
+                    let busy = radio.channel_busy()?;
+                    if busy && !node.backoff.exhausted() {
+                        let random = node.random();
+                        let at = node.backoff.busy(random, now);
+                        info!("channel busy, next look at {}", at);
+                    } else if let Some(frame) = &node.outgoing {
+                        if busy {
+                            info!("channel still busy, sending anyway");
+                        }
+                        // the frame stays queued when the radio fails to take it
+                        radio.start_transmit(frame)?;
+                        node.outgoing = None;
+                        node.backoff.sent();
+                        node.duty.record(airtime.unwrap_or(0), now);
+                        return Ok(State::Sending);
+                    }
+                }
+                match radio.check_receive(false)? {
+                    true => Ok(State::Received), //have a valid packet in the buffer
+                    false => Ok(State::Idle),    //got an invalid packet
+                }
+            }
+            State::Sending => match radio.check_transmit()? {
+                true => {
+                    node.resets = 0;
+                    Ok(State::SendingDone)
+                }
+                false => Ok(State::Sending),
+            },
+            State::Received => {
+                let mut buff = [0u8; MAX_PACKET];
+                let (len, info) = radio.get_received(&mut buff)?;
+                node.resets = 0;
+                info!(
+                    "received packet len = {} info : {} {}",
+                    len,
+                    info.rssi(),
+                    info.snr()
+                );
+                match frame::decode(&buff[..len]) {
+                    Ok(frame) if frame.header.src == node.address => {
+                        info!("ignoring our own frame");
+                    }
+                    Ok(frame) => {
+                        let header = frame.header;
+                        let mut plain = [0u8; MAX_PAYLOAD];
+                        let opened = node.open(&header, frame.payload, &mut plain);
+                        // a sender without the channel key could claim any path
+                        if opened.is_ok() {
+                            overheard(node, &header, &info, now);
+                        }
+                        // nor have us flood what we know to be forged
+                        let genuine = opened.is_ok() || node.key.is_none();
+                        if genuine
+                            && header.dst != node.address
+                            && (header.next == BROADCAST || header.next == node.address)
+                        {
+                            let next = node.routes.next_hop(header.dst);
+                            let jitter = node.random() % RELAY_JITTER_MS;
+                            let at = now.wrapping_add(RELAY_DELAY_MS + jitter);
+                            node.mesh
+                                .offer(&header, frame.payload, node.address, next, at);
+                        }
+                        match opened {
+                            _ if !header.is_for(node.address) => {
+                                info!("frame for {}", header.dst);
+                            }
+                            Ok((epoch, payload)) => {
+                                receive(node, disp, &header, epoch, payload, &info, now)
+                            }
+                            Err(e) => {
+                                node.rejected = node.rejected.wrapping_add(1);
+                                info!("rejected frame from {}: {}", header.src, e);
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        info!("dropping malformed frame {}", e);
+                    }
+                }
+                Ok(State::PrepareIdle)
+            }
+            State::SendingDone => {
+                radio.start_receive()?;
+                Ok(Self::Idle)
+            }
+        }
+    }
+
+    /// Pick the state to go to after `self` failed with `e`.
+    ///
+    /// Errors that leave the chip in an unknown state go through `Reset`,
+    /// counting attempts in `node` until a frame is sent or received, so a
+    /// dead radio ends up in `Failed` instead of looping.
+    pub fn on_error<E: RadioError>(&self, e: E, node: &mut Node, now: u32) -> State {
+        match e.recovery() {
+            Recovery::Idle => State::PrepareIdle,
+            Recovery::Reset => {
+                let attempt = node.resets;
+                node.resets = attempt.saturating_add(1);
+                State::Reset {
+                    attempt,
+                    at: now.wrapping_add(RESET_BACKOFF_MS << attempt.min(4)),
+                }
+            }
+            Recovery::Fatal(why) => panic!("{}", why),
+        }
+    }
+}
+
+/// Pick the next frame to send, by priority, and put it in `node.outgoing`.
+fn stage<const N: usize>(
+    node: &mut Node,
+    outbox: &mut Outbox<N>,
+    disp: &mut impl Interface,
+    now: u32,
+) {
+    if let Some((to, seq)) = node.acks.pop_front() {
+        info!("Ack {} to {}", seq, to);
+        let header = Header::new(node.address, to, node.next_seq(), Kind::Ack, 0);
+        queue(node, node.epoch(), header, &seq.to_le_bytes());
+        return;
+    }
+    if let Some(frame) = node.mesh.due(now) {
+        info!("Relay frame");
+        node.outgoing = Some(frame);
+        return;
+    }
+    if reached(now, node.routes.advertise_at) {
+        info!("Advertise routes");
+        node.routes.expire(now);
+        let jitter = node.random() % (ADVERTISE_MS / 8);
+        node.routes.advertise_at = now.wrapping_add(ADVERTISE_MS + jitter);
+        let mut payload = [0u8; MAX_SINGLE];
+        let len = node.routes.advertisement(node.address, &mut payload);
+        let header = Header {
+            // neighbours only
+            ttl: 0,
+            ..Header::new(node.address, BROADCAST, node.next_seq(), Kind::Routes, 0)
+        };
+        queue(node, node.epoch(), header, &payload[..len]);
+        return;
+    }
+    if node.beacon_interval_ms != 0 && reached(now, node.beacon_at) {
+        info!("Presence beacon");
+        let interval = node.beacon_interval_ms;
+        let jitter = node.random() % (interval / 8).max(1);
+        node.beacon_at = now.wrapping_add(interval + jitter);
+        let mut payload = [0u8; MAX_SINGLE];
+        let len = node.presence.encode(&mut payload);
+        let header = Header {
+            ttl: 0,
+            ..Header::new(node.address, BROADCAST, node.next_seq(), Kind::Presence, 0)
+        };
+        queue(node, node.epoch(), header, &payload[..len]);
+        return;
+    }
+    if let Some(burst) = &mut node.burst {
+        let index = burst.next;
+        let mut payload = [0u8; MAX_PAYLOAD];
+        let len = fragment::write(&burst.body, index, &mut payload);
+        let header = Header::new(
+            node.address,
+            burst.to,
+            burst.seq.wrapping_add(index as u16),
+            Kind::Chat,
+            burst.flags | FLAG_FRAGMENT,
+        );
+        let epoch = burst.epoch;
+        burst.next += 1;
+        if burst.next == fragment::count(burst.body.len()) {
+            node.burst = None;
+        }
+        info!("Send fragment {}", index);
+        queue(node, epoch, header, &payload[..len]);
+        return;
+    }
+    if let Some(i) = node.expired(now) {
+        let timeout = ack_timeout(node.radio(), node.pending[i].body.len());
+        let pending = &mut node.pending[i];
+        let seq = pending.seq;
+        if pending.retries >= MAX_RETRIES {
+            info!("no ack for {}", seq);
+            node.pending.swap_remove(i);
+            disp.set_delivery(seq, Delivery::Failed);
+            return;
+        }
+        info!("Resend packet {}", seq);
+        let count = fragment::count(pending.body.len());
+        pending.retries += 1;
+        pending.deadline = now.wrapping_add(timeout);
+        let (epoch, to) = (pending.epoch, pending.to);
+        if count > 1 {
+            node.burst = Some(Burst {
+                seq,
+                epoch,
+                to,
+                flags: FLAG_ACK,
+                body: pending.body.clone(),
+                next: 0,
+            });
+            return;
+        }
+        let header = Header::new(node.address, to, seq, Kind::Chat, FLAG_ACK);
+        let body = node.pending[i].body.clone();
+        queue(node, epoch, header, &body);
+        return;
+    }
+    if let Some(Outgoing { to, body: packet }) = outbox.front() {
+        info!("Send packet to {}", to);
+        let to = *to;
+        let mut name: String<NAME_LEN> = String::new();
+        _ = name.push_str(node.neighbours.name(to).unwrap_or(&address_name(to)));
+        let to_name = Some(name.as_bytes()).filter(|_| to != BROADCAST);
+        let ack = node.want_ack && !node.pending.is_full();
+        let flags = if ack { FLAG_ACK } else { 0 };
+        let count = fragment::count(packet.len());
+        let seq = node.next_seqs(count as u16);
+        let epoch = node.epoch();
+        if count > 1 {
+            node.burst = Some(Burst {
+                seq,
+                epoch,
+                to,
+                flags,
+                body: packet.clone(),
+                next: 0,
+            });
+        } else {
+            let header = Header::new(node.address, to, seq, Kind::Chat, flags);
+            match seal(node, epoch, header, packet) {
+                Ok(frame) => node.outgoing = Some(frame),
+                Err(e) => {
+                    info!("cannot send {}: {}", seq, e);
+                    disp.add_own(seq, to_name, packet, Delivery::Failed);
+                    outbox.pop_front();
+                    return;
+                }
+            }
+        }
+        if ack {
+            disp.add_own(seq, to_name, packet, Delivery::Pending);
+            _ = node.pending.push(Pending {
+                seq,
+                epoch,
+                to,
+                body: packet.clone(),
+                retries: 0,
+                deadline: now.wrapping_add(ack_timeout(node.radio(), packet.len())),
+            });
+        } else {
+            disp.add_own(seq, to_name, packet, Delivery::Sent);
+        }
+        outbox.pop_front();
+    }
+}
+
+/// Time to wait for the ack of a `len` bytes message: the airtime of the
+/// message and of its ack, doubled to leave room for a relay each way.
+fn ack_timeout(radio: &RadioSettings, len: usize) -> u32 {
+    let (channel, config) = (radio.channel(), radio.lora());
+    let message = message_ms(&channel, &config, len);
+    let ack = message_ms(&channel, &config, core::mem::size_of::<u16>());
+    ACK_TIMEOUT_MS + 2 * (message + ack)
+}
+
+/// Learn neighbours and routes from the path a frame took to reach us.
+fn overheard(node: &mut Node, header: &Header, info: &impl SignalInfo, now: u32) {
+    node.neighbours
+        .heard(header.via, info.rssi(), info.snr(), now);
+    node.routes.expire(now);
+    node.routes.learn(header.via, header.via, 1, now);
+    if header.src != header.via {
+        let hops = DEFAULT_TTL.saturating_sub(header.ttl) + 1;
+        node.routes.learn(header.src, header.via, hops, now);
+    }
+}
+
+/// Act on an authenticated frame addressed to us unless it is a duplicate.
+fn receive(
+    node: &mut Node,
+    disp: &mut impl Interface,
+    header: &Header,
+    epoch: u16,
+    payload: &[u8],
+    info: &impl SignalInfo,
+    now: u32,
+) {
+    node.reassembly.expire(now);
+    let counter = replay::counter(epoch, header.seq);
+    let strict = node.key.is_some();
+    if node.replay.check(header.src, counter, strict, now) {
+        deliver(node, disp, header, payload, info.snr(), info.rssi(), now)
+    } else {
+        node.duplicates = node.duplicates.wrapping_add(1);
+        info!("duplicate {} from {}", header.seq, header.src);
+        reacknowledge(node, header, payload);
+    }
+}
+
+/// Act on an authenticated frame addressed to us.
+fn deliver(
+    node: &mut Node,
+    disp: &mut impl Interface,
+    header: &Header,
+    payload: &[u8],
+    snr: Option<i16>,
+    rssi: i16,
+    now: u32,
+) {
+    match header.kind {
+        Kind::Chat if header.flags & FLAG_FRAGMENT != 0 => {
+            match node.reassembly.add(header.src, header.seq, payload, now) {
+                Ok(Some((seq, message))) => {
+                    let hex = address_name(header.src);
+                    let name = node.neighbours.name(header.src).unwrap_or(&hex);
+                    disp.add_log(Some(name.as_bytes()), &message, snr, Some(rssi));
+                    if header.flags & FLAG_ACK != 0 {
+                        node.owe_ack(header.src, seq);
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    info!(
+                        "dropping fragment {} from {}: {}",
+                        header.seq, header.src, e
+                    );
+                }
+            }
+        }
+        Kind::Chat => {
+            let hex = address_name(header.src);
+            let name = node.neighbours.name(header.src).unwrap_or(&hex);
+            disp.add_log(Some(name.as_bytes()), payload, snr, Some(rssi));
+            if header.flags & FLAG_ACK != 0 {
+                node.owe_ack(header.src, header.seq);
+            }
+        }
+        Kind::Ack => {
+            if let Ok(seq) = payload.try_into().map(u16::from_le_bytes) {
+                if node.acknowledge(seq) {
+                    disp.set_delivery(seq, Delivery::Delivered);
+                }
+            }
+        }
+        Kind::Routes => {
+            let own = node.address;
+            node.routes
+                .learn_advertisement(own, header.src, payload, now);
+        }
+        Kind::Presence => match Presence::decode(payload) {
+            Some(presence) => node.neighbours.announced(header.src, presence),
+            None => info!("bad beacon from {}", header.src),
+        },
+        Kind::Control => {
+            info!("control frame from {}", header.src);
+        }
+    }
+}
+
+/// Acknowledge again a message received twice, our first ack may be the one
+/// that got lost. Fragmented messages only once they were complete.
+fn reacknowledge(node: &mut Node, header: &Header, payload: &[u8]) {
+    if header.kind != Kind::Chat || header.flags & FLAG_ACK == 0 {
+        return;
+    }
+    let seq = if header.flags & FLAG_FRAGMENT != 0 {
+        match fragment::position(payload) {
+            Ok((index, _)) => header.seq.wrapping_sub(index as u16),
+            Err(_) => return,
+        }
+    } else {
+        header.seq
+    };
+    if header.flags & FLAG_FRAGMENT == 0 || node.reassembly.completed(header.src, seq) {
+        node.owe_ack(header.src, seq);
+    }
+}
+
+/// Seal and frame `payload` into `node.outgoing`, nothing is sent when
+/// that fails.
+fn queue(node: &mut Node, epoch: Option<u16>, header: Header, payload: &[u8]) {
+    match seal(node, epoch, header, payload) {
+        Ok(frame) => node.outgoing = Some(frame),
+        Err(e) => info!("cannot send {}: {}", header.seq, e),
+    }
+}
+
+/// Frame `payload` behind `header`, sealing it when `node` has a key and
+/// routing it when a next hop is known.
+fn seal(
+    node: &Node,
+    epoch: Option<u16>,
+    mut header: Header,
+    payload: &[u8],
+) -> Result<Vec<u8, MAX_PACKET>, SealError> {
+    header.next = node.routes.next_hop(header.dst);
+    let mut sealed = [0u8; MAX_PAYLOAD];
+    let payload = match &node.key {
+        Some(key) => {
+            header.flags |= FLAG_ENCRYPTED;
+            let epoch = epoch.ok_or(SealError::Epoch)?;
+            let len = crypto::seal(key, &header, epoch, payload, &mut sealed)
+                .map_err(SealError::Crypto)?;
+            &sealed[..len]
+        }
+        None => payload,
+    };
+    let mut frame = Vec::new();
+    _ = frame.resize_default(MAX_PACKET);
+    let len = frame::encode(&header, payload, &mut frame).map_err(SealError::Frame)?;
+    frame.truncate(len);
+    Ok(frame)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lbt::{MAX_ATTEMPTS, SLOT_MS};
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Timeout;
+
+    impl RadioError for Timeout {
+        fn recovery(&self) -> Recovery {
+            Recovery::Reset
+        }
+    }
+
+    #[derive(Debug, Default)]
+    struct MockInfo;
+
+    impl radio::ReceiveInfo for MockInfo {
+        fn rssi(&self) -> i16 {
+            -60
+        }
+    }
+
+    impl SignalInfo for MockInfo {
+        fn rssi(&self) -> i16 {
+            -60
+        }
+        fn snr(&self) -> Option<i16> {
+            Some(9)
+        }
+    }
+
+    /// Radio that times out on everything while `broken`.
+    #[derive(Default)]
+    struct MockRadio {
+        broken: bool,
+        /// Only transmitting times out.
+        deaf: bool,
+        resets: u8,
+        sent: usize,
+        /// Listen before talk finds the channel busy this many more times.
+        busy: u8,
+        looks: u8,
+    }
+
+    impl MockRadio {
+        fn check(&self) -> Result<(), Timeout> {
+            match self.broken {
+                true => Err(Timeout),
+                false => Ok(()),
+            }
+        }
+    }
+
+    impl Transmit for MockRadio {
+        type Error = Timeout;
+
+        fn start_transmit(&mut self, _data: &[u8]) -> Result<(), Timeout> {
+            self.check()?;
+            if self.deaf {
+                return Err(Timeout);
+            }
+            self.sent += 1;
+            Ok(())
+        }
+
+        fn check_transmit(&mut self) -> Result<bool, Timeout> {
+            self.check().map(|_| true)
+        }
+    }
+
+    impl Receive for MockRadio {
+        type Info = MockInfo;
+        type Error = Timeout;
+
+        fn start_receive(&mut self) -> Result<(), Timeout> {
+            self.check()
+        }
+
+        fn check_receive(&mut self, _restart: bool) -> Result<bool, Timeout> {
+            self.check().map(|_| false)
+        }
+
+        fn get_received(&mut self, _buff: &mut [u8]) -> Result<(usize, MockInfo), Timeout> {
+            self.check().map(|_| (0, MockInfo))
+        }
+    }
+
+    impl ResetRadio for MockRadio {
+        type Error = Timeout;
+
+        fn reset(&mut self, _settings: &RadioSettings) -> Result<(), Timeout> {
+            self.resets += 1;
+            Ok(())
+        }
+    }
+
+    impl ChannelActivity for MockRadio {
+        type Error = Timeout;
+
+        fn channel_busy(&mut self) -> Result<bool, Timeout> {
+            self.check()?;
+            self.looks += 1;
+            let busy = self.busy > 0;
+            self.busy = self.busy.saturating_sub(1);
+            Ok(busy)
+        }
+    }
+
+    #[derive(Default)]
+    struct Screen {
+        logs: usize,
+        failed: usize,
+    }
+
+    impl Interface for Screen {
+        fn set_title(&mut self, _title: &[u8]) {}
+        fn set_input(&mut self, _input: &[u8], _cursor: usize) {}
+        fn set_overlay(&mut self, _overlay: Option<&'static str>) {}
+        fn add_log(&mut self, _from: Option<&[u8]>, _body: &[u8], _: Option<i16>, _: Option<i16>) {
+            self.logs += 1;
+        }
+        fn add_own(&mut self, _id: u16, _to: Option<&[u8]>, _body: &[u8], status: Delivery) {
+            self.failed += (status == Delivery::Failed) as usize;
+        }
+        fn set_delivery(&mut self, _id: u16, _status: Delivery) {}
+    }
+
+    struct Bench {
+        radio: MockRadio,
+        node: Node,
+        outbox: Outbox<2>,
+        screen: Screen,
+        state: State,
+        now: u32,
+    }
+
+    impl Bench {
+        fn new() -> Self {
+            Self {
+                radio: MockRadio::default(),
+                node: Node::new(1),
+                outbox: Outbox::new(),
+                screen: Screen::default(),
+                state: State::Init,
+                now: 0,
+            }
+        }
+
+        /// Run the state machine a millisecond at a time until `done`.
+        fn run_until(&mut self, done: impl Fn(&Self) -> bool) {
+            for _ in 0..10_000 {
+                if done(self) {
+                    return;
+                }
+                let next = self.state.run_state(
+                    &mut self.radio,
+                    &mut self.node,
+                    &mut self.outbox,
+                    &mut self.screen,
+                    self.now,
+                );
+                self.state = match next {
+                    Ok(state) => state,
+                    Err(e) => self.state.on_error(e, &mut self.node, self.now),
+                };
+                self.now += 1;
+            }
+            panic!("gave up waiting");
+        }
+    }
+
+    #[test]
+    fn dead_radio_fails_after_bounded_resets() {
+        let mut bench = Bench::new();
+        bench.radio.broken = true;
+        // every reset succeeds but the next receive times out again
+        bench.run_until(|b| b.state == State::Failed);
+        assert_eq!(bench.radio.resets, RESET_ATTEMPTS);
+        assert_eq!(bench.screen.logs, 2);
+        let now = bench.now;
+        bench.run_until(|b| b.now == now + 100);
+        assert!(bench.state == State::Failed);
+        assert_eq!(bench.radio.resets, RESET_ATTEMPTS);
+    }
+
+    #[test]
+    fn resets_are_counted_until_a_frame_goes_out() {
+        let mut bench = Bench::new();
+        bench.node.beacon_interval_ms = 0;
+        bench.node.routes.advertise_at = 1_000_000;
+        bench.radio.broken = true;
+        bench.run_until(|b| b.radio.resets == 2);
+        assert_eq!(bench.node.resets, 2);
+        // the radio comes back, idling does not clear the count
+        bench.radio.broken = false;
+        bench.run_until(|b| b.state == State::Idle);
+        let now = bench.now;
+        bench.run_until(|b| b.now == now + 100);
+        assert_eq!(bench.node.resets, 2);
+        _ = bench
+            .outbox
+            .push_back(Outgoing::broadcast(Packet::from_slice(b"hello").unwrap()));
+        bench.run_until(|b| b.state == State::SendingDone);
+        assert_eq!(bench.radio.sent, 1);
+        assert_eq!(bench.node.resets, 0);
+        bench.radio.broken = true;
+        bench.run_until(|b| matches!(b.state, State::Reset { .. }));
+        assert!(matches!(bench.state, State::Reset { attempt: 0, .. }));
+    }
+
+    #[test]
+    fn frame_refused_by_the_radio_stays_queued() {
+        let mut bench = Bench::new();
+        bench.node.beacon_interval_ms = 0;
+        bench.node.routes.advertise_at = 1_000_000;
+        bench.node.want_ack = false;
+        bench.radio.deaf = true;
+        _ = bench
+            .outbox
+            .push_back(Outgoing::broadcast(Packet::from_slice(b"hello").unwrap()));
+        bench.run_until(|b| matches!(b.state, State::Reset { .. }));
+        assert!(bench.node.outgoing.is_some());
+        assert_eq!(bench.node.duty.used(bench.now), 0);
+        bench.radio.deaf = false;
+        bench.run_until(|b| b.state == State::SendingDone);
+        assert_eq!(bench.radio.sent, 1);
+        assert!(bench.node.outgoing.is_none());
+        assert!(bench.node.duty.used(bench.now) > 0);
+    }
+
+    #[test]
+    fn sealed_frames_wait_for_an_epoch() {
+        let mut bench = Bench::new();
+        bench.node.beacon_interval_ms = 0;
+        bench.node.routes.advertise_at = 1_000_000;
+        bench.node.key = Some([7; crypto::KEY_LEN]);
+        _ = bench
+            .outbox
+            .push_back(Outgoing::broadcast(Packet::from_slice(b"hello").unwrap()));
+        bench.run_until(|b| b.outbox.is_empty());
+        assert_eq!(bench.screen.failed, 1);
+        assert!(bench.node.outgoing.is_none());
+        bench.node.set_epoch(Some(1));
+        _ = bench
+            .outbox
+            .push_back(Outgoing::broadcast(Packet::from_slice(b"hello").unwrap()));
+        bench.run_until(|b| b.state == State::SendingDone);
+        assert_eq!(bench.radio.sent, 1);
+        assert_eq!(bench.screen.failed, 1);
+    }
+
+    #[test]
+    fn typed_recipients() {
+        let mut node = Node::new(1);
+        node.neighbours.heard(0x00ad, -60, None, 0);
+        let mut presence = Presence::default();
+        _ = presence.name.push_str("ada");
+        node.neighbours.announced(0x00ad, presence);
+        let parse = |input: &[u8]| {
+            Outgoing::from_input(&node, input).map(|message| (message.to, message.body))
+        };
+        let body = |text: &[u8]| Packet::from_slice(text).unwrap();
+        assert_eq!(parse(b"hello"), Some((BROADCAST, body(b"hello"))));
+        assert_eq!(parse(b"@ada hi there"), Some((0x00ad, body(b"hi there"))));
+        assert_eq!(parse(b"@12ab hi"), Some((0x12ab, body(b"hi"))));
+        assert_eq!(parse(b"@ada"), Some((0x00ad, body(b""))));
+        assert_eq!(parse(b"@bob hi"), None);
+        assert_eq!(parse(b"@ hi"), None);
+    }
+
+    #[test]
+    fn busy_channel_delays_the_frame() {
+        let mut bench = Bench::new();
+        bench.node.beacon_interval_ms = 0;
+        bench.node.routes.advertise_at = 1_000_000;
+        bench.radio.busy = 3;
+        bench.run_until(|b| b.state == State::Idle);
+        _ = bench
+            .outbox
+            .push_back(Outgoing::broadcast(Packet::from_slice(b"hello").unwrap()));
+        let start = bench.now;
+        bench.run_until(|b| b.state == State::SendingDone);
+        assert_eq!(bench.radio.looks, 4);
+        assert_eq!(bench.radio.sent, 1);
+        // one slot at least and the whole window at most after each look
+        let waited = bench.now - start;
+        assert!(waited >= 3 * SLOT_MS, "{}", waited);
+        assert!(waited <= (1 + 2 + 4) * SLOT_MS + 10, "{}", waited);
+    }
+
+    #[test]
+    fn jammed_channel_is_used_anyway() {
+        let mut bench = Bench::new();
+        bench.node.beacon_interval_ms = 0;
+        bench.node.routes.advertise_at = 1_000_000;
+        bench.radio.busy = u8::MAX;
+        _ = bench
+            .outbox
+            .push_back(Outgoing::broadcast(Packet::from_slice(b"hello").unwrap()));
+        bench.run_until(|b| b.state == State::SendingDone);
+        assert_eq!(bench.radio.looks, MAX_ATTEMPTS + 1);
+        assert_eq!(bench.radio.sent, 1);
+    }
+}
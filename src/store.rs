@@ -0,0 +1,568 @@
+//! Small key/value store kept in a few flash sectors.
+//!
+//! Values are appended as records to the active sector, the last record of
+//! a key holding its value. When the sector is full, the latest value of
+//! every key, the one being set included, is copied to the next sector,
+//! which becomes active once its header is written, so erases go round the
+//! sectors and a power loss at any point leaves either the old or the new
+//! sector valid.
+//!
+//! ```text
+//! sector: | magic (2) | schema (1) | 0xFF | generation (4) | record ...
+//! record: | key | len | crc (2) | value ...
+//! ```
+//! Sectors written with another [`SCHEMA_VERSION`] are ignored, the store
+//! starting over empty.
+#![allow(dead_code)]
+
+use heapless::Vec;
+
+/// Bump when the meaning of stored values changes.
+pub const SCHEMA_VERSION: u8 = 1;
+/// Longest value of a key.
+pub const MAX_VALUE: usize = 64;
+/// Distinct keys the store can hold.
+pub const MAX_KEYS: usize = 16;
+const MAGIC: [u8; 2] = *b"KV";
+const SECTOR_HEADER: usize = 8;
+const RECORD_HEADER: usize = 4;
+/// Key of erased flash, marks the end of the records.
+const FREE: u8 = 0xFF;
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum StoreError {
+    TooLong,
+    /// `0xFF` is the marker of erased flash.
+    ReservedKey,
+    /// The latest values do not fit in a sector.
+    Full,
+}
+
+/// Flash region the store lives in, a whole number of sectors.
+pub trait Flash {
+    /// Smallest erasable unit.
+    const SECTOR_SIZE: usize;
+    fn capacity(&self) -> usize;
+    fn read(&mut self, offset: usize, buf: &mut [u8]);
+    /// Set the sector starting at `offset` to `0xFF`.
+    fn erase(&mut self, offset: usize);
+    /// Program `data` at `offset`, which can only clear bits.
+    fn write(&mut self, offset: usize, data: &[u8]);
+}
+
+/// [`Flash`] in RAM, behaving like NOR flash, to exercise the store on
+/// the host.
+pub struct RamFlash<const N: usize> {
+    pub data: [u8; N],
+    /// Bytes left to program before the power is cut, every write after
+    /// that being lost or torn.
+    pub power: Option<usize>,
+    /// Sector erases so far, to check wear levelling.
+    pub erases: u32,
+}
+
+impl<const N: usize> RamFlash<N> {
+    pub fn new() -> Self {
+        Self {
+            data: [0xFF; N],
+            power: None,
+            erases: 0,
+        }
+    }
+}
+
+impl<const N: usize> Default for RamFlash<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Flash for RamFlash<N> {
+    const SECTOR_SIZE: usize = 4096;
+
+    fn capacity(&self) -> usize {
+        N
+    }
+
+    fn read(&mut self, offset: usize, buf: &mut [u8]) {
+        buf.copy_from_slice(&self.data[offset..offset + buf.len()]);
+    }
+
+    fn erase(&mut self, offset: usize) {
+        if self.power == Some(0) {
+            return;
+        }
+        self.data[offset..offset + Self::SECTOR_SIZE].fill(0xFF);
+        self.erases += 1;
+    }
+
+    fn write(&mut self, offset: usize, data: &[u8]) {
+        for (i, byte) in data.iter().enumerate() {
+            match &mut self.power {
+                Some(0) => return,
+                Some(left) => *left -= 1,
+                None => {}
+            }
+            self.data[offset + i] &= byte;
+        }
+    }
+}
+
+enum Record {
+    End,
+    /// Torn or corrupted, skipped.
+    Bad {
+        next: usize,
+    },
+    Valid {
+        key: u8,
+        len: usize,
+        next: usize,
+    },
+}
+
+pub struct Store<F: Flash> {
+    flash: F,
+    /// Offset of the active sector.
+    active: usize,
+    generation: u32,
+    /// Where the next record goes.
+    head: usize,
+}
+
+impl<F: Flash> Store<F> {
+    /// Open the store in `flash`, starting it empty when no sector is valid.
+    pub fn mount(flash: F) -> Self {
+        let mut store = Self {
+            flash,
+            active: 0,
+            generation: 0,
+            head: 0,
+        };
+        let mut found = false;
+        for sector in (0..store.sectors()).map(|i| i * F::SECTOR_SIZE) {
+            match store.header(sector) {
+                Some(generation) if !found || generation > store.generation => {
+                    store.active = sector;
+                    store.generation = generation;
+                    found = true;
+                }
+                _ => {}
+            }
+        }
+        if !found {
+            store.format(0, 1);
+        }
+        store.head = store.active + SECTOR_HEADER;
+        loop {
+            match store.record(store.active, store.head) {
+                Record::End => break,
+                Record::Bad { next } | Record::Valid { next, .. } => store.head = next,
+            }
+        }
+        store
+    }
+
+    /// `true` when nothing was ever written, or since the store started
+    /// over. Torn records count as written.
+    pub fn is_empty(&self) -> bool {
+        self.head == self.active + SECTOR_HEADER
+    }
+
+    pub fn flash(&mut self) -> &mut F {
+        &mut self.flash
+    }
+
+    fn sectors(&self) -> usize {
+        self.flash.capacity() / F::SECTOR_SIZE
+    }
+
+    /// Generation of the sector at `offset`, `None` when it is not ours.
+    fn header(&mut self, offset: usize) -> Option<u32> {
+        let mut header = [0u8; SECTOR_HEADER];
+        self.flash.read(offset, &mut header);
+        if header[0..2] != MAGIC || header[2] != SCHEMA_VERSION {
+            return None;
+        }
+        Some(u32::from_le_bytes([
+            header[4], header[5], header[6], header[7],
+        ]))
+    }
+
+    fn format(&mut self, offset: usize, generation: u32) {
+        self.flash.erase(offset);
+        self.write_header(offset, generation);
+        self.active = offset;
+        self.generation = generation;
+        self.head = offset + SECTOR_HEADER;
+    }
+
+    fn write_header(&mut self, offset: usize, generation: u32) {
+        // the magic goes last, a torn header is not taken for a valid one
+        self.flash.write(offset + 4, &generation.to_le_bytes());
+        self.flash
+            .write(offset, &[MAGIC[0], MAGIC[1], SCHEMA_VERSION]);
+    }
+
+    /// Read the record at `offset` of the sector starting at `sector`.
+    fn record(&mut self, sector: usize, offset: usize) -> Record {
+        let end = sector + F::SECTOR_SIZE;
+        if offset + RECORD_HEADER > end {
+            return Record::End;
+        }
+        let mut header = [0u8; RECORD_HEADER];
+        self.flash.read(offset, &mut header);
+        let [key, len, crc0, crc1] = header;
+        let len = len as usize;
+        let next = offset + RECORD_HEADER + len;
+        if key == FREE {
+            return Record::End;
+        }
+        if len > MAX_VALUE || next > end {
+            // the length itself is torn, nothing after it can be trusted
+            return Record::Bad { next: end };
+        }
+        let mut value = [0u8; MAX_VALUE];
+        self.flash.read(offset + RECORD_HEADER, &mut value[..len]);
+        if record_crc(key, &value[..len]) != u16::from_le_bytes([crc0, crc1]) {
+            return Record::Bad { next };
+        }
+        Record::Valid { key, len, next }
+    }
+
+    /// Offset and length of the latest value of `key` in `sector`.
+    fn find(&mut self, sector: usize, key: u8) -> Option<(usize, usize)> {
+        let mut offset = sector + SECTOR_HEADER;
+        let mut found = None;
+        loop {
+            match self.record(sector, offset) {
+                Record::End => return found,
+                Record::Bad { next } => offset = next,
+                Record::Valid { key: k, len, next } => {
+                    if k == key {
+                        found = Some((offset + RECORD_HEADER, len));
+                    }
+                    offset = next;
+                }
+            }
+        }
+    }
+
+    /// Copy the value of `key` to `out`, returning its length.
+    pub fn get(&mut self, key: u8, out: &mut [u8]) -> Option<usize> {
+        let (offset, len) = self.find(self.active, key)?;
+        let len = len.min(out.len());
+        self.flash.read(offset, &mut out[..len]);
+        Some(len)
+    }
+
+    pub fn set(&mut self, key: u8, value: &[u8]) -> Result<(), StoreError> {
+        if key == FREE {
+            return Err(StoreError::ReservedKey);
+        }
+        if value.len() > MAX_VALUE {
+            return Err(StoreError::TooLong);
+        }
+        let mut current = [0u8; MAX_VALUE];
+        if self.get(key, &mut current) == Some(value.len()) && current[..value.len()] == *value {
+            // spare the flash
+            return Ok(());
+        }
+        if self.head + RECORD_HEADER + value.len() > self.active + F::SECTOR_SIZE {
+            return self.compact(key, value);
+        }
+        self.head = self.append(self.head, key, value);
+        Ok(())
+    }
+
+    /// Write a record at `offset`, returning where the next one goes.
+    fn append(&mut self, offset: usize, key: u8, value: &[u8]) -> usize {
+        let mut record: Vec<u8, { RECORD_HEADER + MAX_VALUE }> = Vec::new();
+        _ = record.extend_from_slice(&[key, value.len() as u8]);
+        _ = record.extend_from_slice(&record_crc(key, value).to_le_bytes());
+        _ = record.extend_from_slice(value);
+        self.flash.write(offset, &record);
+        offset + record.len()
+    }
+
+    /// Move the latest values to the next sector, `key` taking `value`
+    /// there, so that a power loss leaves either all old or all new values.
+    fn compact(&mut self, key: u8, value: &[u8]) -> Result<(), StoreError> {
+        let old = self.active;
+        let mut keys: Vec<u8, MAX_KEYS> = Vec::new();
+        let mut offset = old + SECTOR_HEADER;
+        loop {
+            match self.record(old, offset) {
+                Record::End => break,
+                Record::Bad { next } => offset = next,
+                Record::Valid { key: k, next, .. } => {
+                    if k != key && !keys.contains(&k) {
+                        keys.push(k).map_err(|_| StoreError::Full)?;
+                    }
+                    offset = next;
+                }
+            }
+        }
+        let sector = (old + F::SECTOR_SIZE) % (self.sectors() * F::SECTOR_SIZE);
+        self.flash.erase(sector);
+        let mut head = sector + SECTOR_HEADER;
+        for k in keys {
+            let (offset, len) = match self.find(old, k) {
+                Some(found) => found,
+                None => continue,
+            };
+            if head + RECORD_HEADER + len > sector + F::SECTOR_SIZE {
+                return Err(StoreError::Full);
+            }
+            let mut copy = [0u8; MAX_VALUE];
+            self.flash.read(offset, &mut copy[..len]);
+            head = self.append(head, k, &copy[..len]);
+        }
+        if head + RECORD_HEADER + value.len() > sector + F::SECTOR_SIZE {
+            return Err(StoreError::Full);
+        }
+        head = self.append(head, key, value);
+        // the header goes last, the old sector stays valid until then
+        let generation = self.generation.wrapping_add(1);
+        self.write_header(sector, generation);
+        self.active = sector;
+        self.generation = generation;
+        self.head = head;
+        Ok(())
+    }
+}
+
+fn record_crc(key: u8, value: &[u8]) -> u16 {
+    crc16(crc16(CRC_INIT, &[key]), value)
+}
+
+/// Start value of [`crc16`].
+pub(crate) const CRC_INIT: u16 = 0xFFFF;
+
+/// CRC-16/CCITT-FALSE, continued from `crc` over `bytes`.
+pub(crate) fn crc16(mut crc: u16, bytes: &[u8]) -> u16 {
+    for byte in bytes {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// The sectors reserved at the end of the RP2040 flash by `memory.x`.
+#[cfg(feature = "firmware")]
+pub struct Rp2040Flash {
+    /// Offset of the region from the start of the flash.
+    start: u32,
+    len: usize,
+}
+
+#[cfg(feature = "firmware")]
+impl Rp2040Flash {
+    /// Where the flash is mapped for reading.
+    const XIP_BASE: usize = 0x1000_0000;
+
+    /// Settings region, keep in sync with `SETTINGS` in `memory.x`.
+    pub fn settings() -> Self {
+        Self {
+            start: 2048 * 1024 - 8 * 1024,
+            len: 8 * 1024,
+        }
+    }
+
+    /// Message history region, keep in sync with `HISTORY` in `memory.x`.
+    pub fn history() -> Self {
+        Self {
+            start: 2048 * 1024 - 72 * 1024,
+            len: 64 * 1024,
+        }
+    }
+}
+
+#[cfg(feature = "firmware")]
+impl Flash for Rp2040Flash {
+    const SECTOR_SIZE: usize = 4096;
+
+    fn capacity(&self) -> usize {
+        self.len
+    }
+
+    fn read(&mut self, offset: usize, buf: &mut [u8]) {
+        let address = Self::XIP_BASE + self.start as usize + offset;
+        for (i, byte) in buf.iter_mut().enumerate() {
+            *byte = unsafe { core::ptr::read_volatile((address + i) as *const u8) };
+        }
+    }
+
+    fn erase(&mut self, offset: usize) {
+        let address = self.start + offset as u32;
+        // nothing may run from flash while it is erased
+        cortex_m::interrupt::free(|_| unsafe {
+            rp2040_flash::flash::flash_range_erase(address, Self::SECTOR_SIZE as u32, true);
+        });
+    }
+
+    fn write(&mut self, offset: usize, data: &[u8]) {
+        // the flash programs whole pages, bytes left at 0xFF stay unchanged
+        const PAGE: usize = 256;
+        let mut at = offset;
+        while at < offset + data.len() {
+            let page = at / PAGE * PAGE;
+            let end = (page + PAGE).min(offset + data.len());
+            let mut buffer = [0xFFu8; PAGE];
+            buffer[at - page..end - page].copy_from_slice(&data[at - offset..end - offset]);
+            let address = self.start + page as u32;
+            cortex_m::interrupt::free(|_| unsafe {
+                rp2040_flash::flash::flash_range_program(address, &buffer, true);
+            });
+            at = end;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECTOR: usize = 4096;
+    type Ram = RamFlash<{ 3 * SECTOR }>;
+
+    fn value(store: &mut Store<Ram>, key: u8) -> Option<Vec<u8, MAX_VALUE>> {
+        let mut out = [0u8; MAX_VALUE];
+        let len = store.get(key, &mut out)?;
+        Vec::from_slice(&out[..len]).ok()
+    }
+
+    fn remount(store: Store<Ram>) -> Store<Ram> {
+        let mut flash = store.flash;
+        flash.power = None;
+        Store::mount(flash)
+    }
+
+    #[test]
+    fn values_survive_a_remount() {
+        let mut store = Store::mount(Ram::new());
+        assert!(store.is_empty());
+        store.set(1, b"one").unwrap();
+        store.set(2, b"").unwrap();
+        store.set(1, b"uno").unwrap();
+        assert!(!store.is_empty());
+        let mut store = remount(store);
+        assert_eq!(value(&mut store, 1).as_deref(), Some(&b"uno"[..]));
+        assert_eq!(value(&mut store, 2).as_deref(), Some(&b""[..]));
+        assert_eq!(value(&mut store, 3), None);
+        assert_eq!(store.set(FREE, b""), Err(StoreError::ReservedKey));
+        assert_eq!(store.set(1, &[0; MAX_VALUE + 1]), Err(StoreError::TooLong));
+    }
+
+    #[test]
+    fn sectors_take_turns() {
+        let mut store = Store::mount(Ram::new());
+        store.set(9, b"kept").unwrap();
+        // enough records to go round the sectors twice
+        let records = 2 * 3 * SECTOR / (RECORD_HEADER + 4);
+        for i in 0..records as u32 {
+            store.set(1, &i.to_le_bytes()).unwrap();
+        }
+        let erases = store.flash.erases;
+        assert!(erases >= 6, "{}", erases);
+        let mut store = remount(store);
+        let last = (records as u32 - 1).to_le_bytes();
+        assert_eq!(value(&mut store, 1).as_deref(), Some(&last[..]));
+        assert_eq!(value(&mut store, 9).as_deref(), Some(&b"kept"[..]));
+        // formatting the first sector was the first erase
+        assert_eq!(store.generation, erases);
+    }
+
+    #[test]
+    fn corrupted_record_is_skipped() {
+        let mut store = Store::mount(Ram::new());
+        store.set(1, b"old").unwrap();
+        let torn = store.head;
+        store.set(1, b"new").unwrap();
+        store.set(2, b"after").unwrap();
+        // a bit of the value cleared, as a torn write would
+        store.flash.data[torn + RECORD_HEADER] = 0;
+        let mut store = remount(store);
+        assert_eq!(value(&mut store, 1).as_deref(), Some(&b"old"[..]));
+        assert_eq!(value(&mut store, 2).as_deref(), Some(&b"after"[..]));
+        assert!(!store.is_empty());
+    }
+
+    #[test]
+    fn other_schema_starts_over() {
+        let mut store = Store::mount(Ram::new());
+        store.set(1, b"one").unwrap();
+        store.flash.data[2] = SCHEMA_VERSION + 1;
+        let mut store = remount(store);
+        assert!(store.is_empty());
+        assert_eq!(value(&mut store, 1), None);
+    }
+
+    /// A store about to compact, holding `1 = old` and `2 = kept`.
+    fn nearly_full() -> Ram {
+        let mut store = Store::mount(Ram::new());
+        store.set(2, b"kept").unwrap();
+        let mut i = 0u32;
+        while store.head + 2 * (RECORD_HEADER + 4) <= store.active + SECTOR {
+            store.set(1, &i.to_le_bytes()).unwrap();
+            i += 1;
+        }
+        store.set(1, b"old").unwrap();
+        store.flash
+    }
+
+    #[test]
+    fn power_cut_at_every_byte_keeps_a_value() {
+        for compacting in [false, true] {
+            let flash = if compacting {
+                nearly_full()
+            } else {
+                let mut store = Store::mount(Ram::new());
+                store.set(2, b"kept").unwrap();
+                store.set(1, b"old").unwrap();
+                store.flash
+            };
+            // bytes programmed by an uninterrupted write
+            let mut store = Store::mount(Ram {
+                data: flash.data,
+                power: Some(usize::MAX),
+                erases: 0,
+            });
+            store.set(1, b"brand new").unwrap();
+            let written = usize::MAX - store.flash.power.unwrap();
+            assert!(!compacting || store.flash.erases == 1);
+
+            for cut in 0..=written {
+                let mut store = Store::mount(Ram {
+                    data: flash.data,
+                    power: Some(cut),
+                    erases: 0,
+                });
+                _ = store.set(1, b"brand new");
+                let mut store = remount(store);
+                let one = value(&mut store, 1);
+                assert!(
+                    matches!(one.as_deref(), Some(b"old") | Some(b"brand new")),
+                    "cut at {}: {:?}",
+                    cut,
+                    one
+                );
+                if cut == written {
+                    assert_eq!(one.as_deref(), Some(&b"brand new"[..]));
+                }
+                assert_eq!(value(&mut store, 2).as_deref(), Some(&b"kept"[..]));
+                // and the store goes on
+                store.set(1, b"later").unwrap();
+                let mut store = remount(store);
+                assert_eq!(value(&mut store, 1).as_deref(), Some(&b"later"[..]));
+            }
+        }
+    }
+}
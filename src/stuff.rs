@@ -5,19 +5,13 @@ use embedded_hal_compat::eh0_2::spi::{Mode, Phase, Polarity};
 
 use panic_probe as _;
 
+use lora_rust::crypto::{parse_key, Key};
+use lora_rust::frame::{parse_address, Address};
+
 // Provide an alias for our BSP so we can switch targets quickly.
 // Uncomment the BSP you included in Cargo.toml, the rest of the code does not need to change.
 // use sparkfun_pro_micro_rp2040 as bsp;
 
-use radio_sx127x::Error as sx127xError;
-use radio_sx127x::{
-    device::lora::{
-        Bandwidth, CodingRate, FrequencyHopping, LoRaChannel, LoRaConfig, PayloadCrc,
-        PayloadLength, SpreadingFactor,
-    },
-    device::{Channel, Modem, PaConfig, PaSelect},
-}; // Error name conflict with hals
-
 //use hal::spidev::{self, SpidevOptions};
 //use hal::sysfs_gpio::Direction;
 //use bsp::hal::Delay;
@@ -58,81 +52,38 @@ impl shift_register::CycleDelay for Delay10Mhz {
     }
 }
 
-pub const MODE: Mode = Mode {
-    //  SPI mode for radio
-    phase: Phase::CaptureOnSecondTransition,
-    polarity: Polarity::IdleHigh,
-};
-
-pub const FREQUENCY: u32 = 433_400_000; // frequency in hertz ch_12: 915_000_000, ch_2: 907_400_000
-
-pub const CONFIG_CH: LoRaChannel = LoRaChannel {
-    freq: FREQUENCY as u32, // frequency in hertz
-    bw: Bandwidth::Bw125kHz,
-    sf: SpreadingFactor::Sf7,
-    cr: CodingRate::Cr4_8,
+/// Address of this node, give each device its own with `NODE_ADDRESS=1a2b cargo run`.
+pub const NODE_ADDRESS: Address = match option_env!("NODE_ADDRESS") {
+    Some(address) => match parse_address(address) {
+        Some(address) => address,
+        None => panic!("NODE_ADDRESS must be up to 4 hexadecimal digits"),
+    },
+    None => 0x0001,
 };
 
-pub const CONFIG_LORA: LoRaConfig = LoRaConfig {
-    preamble_len: 0x8,
-    symbol_timeout: 0x64,
-    payload_len: PayloadLength::Variable,
-    payload_crc: PayloadCrc::Enabled,
-    frequency_hop: FrequencyHopping::Disabled,
-    invert_iq: false,
+/// Name announced to neighbours, `NODE_NAME=otter cargo run`, the address
+/// in hex when empty.
+pub const NODE_NAME: &str = match option_env!("NODE_NAME") {
+    Some(name) => name,
+    None => "",
 };
 
-//   compare other settings in python version
-//    lora.set_mode(sx127x_lora::RadioMode::Stdby).unwrap();
-//    set_tx_power(level, output_pin) level >17 => PA_BOOST.
-//    lora.set_tx_power(17,1).unwrap();
-//    lora.set_tx_power(15,1).unwrap();
-
-//baud = 1000000 is this needed for spi or just USART ?
-
-pub const CONFIG_PA: PaConfig = PaConfig {
-    output: PaSelect::Boost,
-    power: 1,
+/// Pre-shared channel key as 64 hex digits, `CHANNEL_KEY=... cargo run`.
+/// Traffic is sent in clear when it is not set.
+pub const CHANNEL_KEY: Option<Key> = match option_env!("CHANNEL_KEY") {
+    Some(key) => match parse_key(key) {
+        Some(key) => Some(key),
+        None => panic!("CHANNEL_KEY must be 64 hexadecimal digits"),
+    },
+    None => None,
 };
 
-//let CONFIG_RADIO = Config::default() ;
-
-pub const CONFIG_RADIO: radio_sx127x::device::Config = radio_sx127x::device::Config {
-    modem: Modem::LoRa(CONFIG_LORA),
-    channel: Channel::LoRa(CONFIG_CH),
-    pa_config: CONFIG_PA,
-    xtal_freq: 32000000, // CHECK
-    timeout_ms: 100,
+pub const MODE: Mode = Mode {
+    //  SPI mode for radio
+    phase: Phase::CaptureOnSecondTransition,
+    polarity: Polarity::IdleHigh,
 };
 
-pub enum State
-//<T>
-//where
-//    T: core::fmt::Debug + 'static,
-{
-    Init,
-    PrepareIdle,
-    Reset,
-    Idle,
-    Sending,
-    SendingDone,
-    Received,
-    //    Error(sx127xError<HalError<T, Infallible, Infallible>>),
-}
-
-pub enum Error<T>
-where
-    T: core::fmt::Debug + 'static,
-{
-    Radio(sx127xError<T>),
-}
-
-impl<T: core::fmt::Debug + 'static> From<sx127xError<T>> for Error<T> {
-    fn from(e: sx127xError<T>) -> Self {
-        Error::Radio(e)
-    }
-}
-
 pub fn copy(src: &[u8], target: &mut [u8], cursor: &mut usize) {
     for c in src {
         if *cursor >= target.len() {
@@ -0,0 +1,112 @@
+#![allow(dead_code)]
+
+use radio_sx127x::{base::Hal, device::PacketInfo, Error as sx127xError, Sx127x};
+use radio_sx127x::{
+    device::lora::{
+        Bandwidth, CodingRate, FrequencyHopping, LoRaChannel, LoRaConfig, PayloadCrc,
+        PayloadLength, SpreadingFactor,
+    },
+    device::{regs, Channel, Modem, PaConfig, PaSelect},
+};
+
+use radio::Rssi;
+
+use crate::lbt::BUSY_THRESHOLD_DBM;
+use crate::settings::RadioSettings;
+use crate::state::{ChannelActivity, RadioError, Recovery, ResetRadio, SignalInfo};
+
+pub const FREQUENCY: u32 = 433_400_000; // frequency in hertz ch_12: 915_000_000, ch_2: 907_400_000
+
+pub const CONFIG_CH: LoRaChannel = LoRaChannel {
+    freq: FREQUENCY as u32, // frequency in hertz
+    bw: Bandwidth::Bw125kHz,
+    sf: SpreadingFactor::Sf7,
+    cr: CodingRate::Cr4_8,
+};
+
+pub const CONFIG_LORA: LoRaConfig = LoRaConfig {
+    preamble_len: 0x8,
+    symbol_timeout: 0x64,
+    payload_len: PayloadLength::Variable,
+    payload_crc: PayloadCrc::Enabled,
+    frequency_hop: FrequencyHopping::Disabled,
+    invert_iq: false,
+};
+
+//   compare other settings in python version
+//    lora.set_mode(sx127x_lora::RadioMode::Stdby).unwrap();
+//    set_tx_power(level, output_pin) level >17 => PA_BOOST.
+//    lora.set_tx_power(17,1).unwrap();
+//    lora.set_tx_power(15,1).unwrap();
+
+//baud = 1000000 is this needed for spi or just USART ?
+
+pub const CONFIG_PA: PaConfig = PaConfig {
+    output: PaSelect::Boost,
+    power: 1,
+};
+
+//let CONFIG_RADIO = Config::default() ;
+
+pub const CONFIG_RADIO: radio_sx127x::device::Config = radio_sx127x::device::Config {
+    modem: Modem::LoRa(CONFIG_LORA),
+    channel: Channel::LoRa(CONFIG_CH),
+    pa_config: CONFIG_PA,
+    xtal_freq: 32000000, // CHECK
+    timeout_ms: 100,
+};
+
+impl<H: Hal> ResetRadio for Sx127x<H> {
+    type Error = sx127xError<<H as Hal>::Error>;
+
+    /// `configure` pulses the reset pin before applying the configuration,
+    /// the sync word is not part of it.
+    fn reset(&mut self, settings: &RadioSettings) -> Result<(), Self::Error> {
+        self.configure(&settings.config())?;
+        self.write_reg(regs::LoRa::SYNCWORD, settings.sync_word)
+    }
+}
+
+impl<H: Hal> ChannelActivity for Sx127x<H> {
+    type Error = sx127xError<<H as Hal>::Error>;
+
+    fn channel_busy(&mut self) -> Result<bool, Self::Error> {
+        Ok(self.poll_rssi()? > BUSY_THRESHOLD_DBM)
+    }
+}
+
+impl SignalInfo for PacketInfo {
+    fn rssi(&self) -> i16 {
+        self.rssi
+    }
+    fn snr(&self) -> Option<i16> {
+        self.snr
+    }
+}
+
+impl<T: core::fmt::Debug> RadioError for sx127xError<T> {
+    fn recovery(&self) -> Recovery {
+        match self {
+            sx127xError::Hal(_) => Recovery::Fatal("HAL problem"),
+            sx127xError::InvalidConfiguration => Recovery::Fatal("invalid Configuration"),
+            sx127xError::Aborted => {
+                info!("Transaction aborted");
+                Recovery::Idle
+            }
+            sx127xError::InvalidResponse => {
+                info!("Invalid response");
+                Recovery::Reset
+            }
+            sx127xError::Timeout => {
+                info!("Timeout");
+                Recovery::Reset
+            }
+            sx127xError::Crc => Recovery::Idle,
+            sx127xError::BufferSize => Recovery::Idle,
+            sx127xError::InvalidDevice(_) => {
+                info!("invalid device, restarting");
+                Recovery::Reset
+            }
+        }
+    }
+}
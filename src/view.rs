@@ -0,0 +1,302 @@
+//! The message log as shown on screen.
+//!
+//! Messages are kept as text, cut to what a single frame carries, and
+//! wrapped to the screen width when drawn, their line count being worked
+//! out once. The viewport is anchored to the newest line unless scrolled
+//! up, in which case new messages do not move it and are counted until the
+//! bottom is reached again.
+#![allow(dead_code)]
+
+use heapless::{Deque, String, Vec};
+
+use crate::fragment::MAX_SINGLE;
+use crate::interface::{LogLine, BIG_WIDTH, DISPLAY_WIDTH, SMALL_WIDTH};
+use crate::neighbours::NAME_LEN;
+
+/// Lines of the viewport.
+pub const LINES: usize = 8;
+/// Messages kept in RAM, older ones are in [`crate::history`].
+pub const MAX_LOG: usize = 12;
+/// Sender, separator and as much of the body as a single frame carries,
+/// longer messages are cut, the history keeping them whole.
+pub const MAX_TEXT: usize = "me>".len() + NAME_LEN + 2 + MAX_SINGLE;
+/// Ends a text that was cut.
+const CUT: &str = "...";
+/// Characters of a line without the signal prefix.
+pub const LINE_CHARS: usize = DISPLAY_WIDTH / BIG_WIDTH;
+
+#[derive(Clone, Default)]
+pub struct Message {
+    /// Small print left of the first line, SNR or delivery.
+    pub up: String<6>,
+    /// Small print below `up`, RSSI.
+    pub down: String<6>,
+    pub text: String<MAX_TEXT>,
+    /// Sequence number of our own message.
+    pub id: Option<u16>,
+    /// Lines once wrapped, kept by [`LogView`].
+    pub(crate) lines: usize,
+}
+
+impl Message {
+    /// Append `s` to the text, ending it with `...` when it does not fit.
+    pub fn push_text(&mut self, s: &str) {
+        for c in s.chars() {
+            if self.text.push(c).is_err() {
+                while self.text.capacity() - self.text.len() < CUT.len() {
+                    self.text.pop();
+                }
+                _ = self.text.push_str(CUT);
+                return;
+            }
+        }
+    }
+
+    /// Cut the message in lines, the first one leaving room for the prefix.
+    fn wrap(&self, mut line: impl FnMut(LogLine)) {
+        let prefix = self.up.len().max(self.down.len()) * SMALL_WIDTH;
+        let mut current = LogLine {
+            up: self.up.clone(),
+            down: self.down.clone(),
+            id: self.id,
+            ..Default::default()
+        };
+        let mut available = (DISPLAY_WIDTH - prefix) / BIG_WIDTH;
+        for c in self.text.chars() {
+            if c == '\r' || c == '\n' || available == 0 {
+                line(core::mem::take(&mut current));
+                available = LINE_CHARS;
+            }
+            if c != '\r' && c != '\n' {
+                _ = current.body.push(c);
+                available -= 1;
+            }
+        }
+        if current != LogLine::default() {
+            line(current);
+        }
+    }
+
+    fn lines(&self) -> usize {
+        let mut count = 0;
+        self.wrap(|_| count += 1);
+        count
+    }
+}
+
+#[derive(Default)]
+pub struct LogView {
+    messages: Deque<Message, MAX_LOG>,
+    /// Lines between the bottom of the viewport and the newest line.
+    offset: usize,
+    /// Messages that arrived while scrolled up.
+    unseen: usize,
+}
+
+impl LogView {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, mut message: Message) {
+        message.lines = message.lines();
+        if self.offset > 0 {
+            // keep showing the same lines
+            self.offset += message.lines;
+            self.unseen += 1;
+        }
+        if self.messages.is_full() {
+            self.messages.pop_front();
+        }
+        _ = self.messages.push_back(message);
+        self.offset = self.offset.min(self.max_offset());
+    }
+
+    /// Set the small print of our message `id`, `true` when it is shown.
+    pub fn set_up(&mut self, id: u16, up: &str) -> bool {
+        let mut found = false;
+        for message in self.messages.iter_mut().filter(|m| m.id == Some(id)) {
+            message.up.clear();
+            _ = message.up.push_str(up);
+            // the prefix may have grown
+            message.lines = message.lines();
+            found = true;
+        }
+        self.offset = self.offset.min(self.max_offset());
+        found
+    }
+
+    fn total(&self) -> usize {
+        self.messages.iter().map(|m| m.lines).sum()
+    }
+
+    fn max_offset(&self) -> usize {
+        self.total().saturating_sub(LINES)
+    }
+
+    /// `true` when the newest line is not shown.
+    pub fn scrolled(&self) -> bool {
+        self.offset > 0
+    }
+
+    pub fn unseen(&self) -> usize {
+        self.unseen
+    }
+
+    /// Show older lines, `false` when already at the oldest.
+    pub fn page_up(&mut self) -> bool {
+        let max = self.max_offset();
+        if self.offset >= max {
+            return false;
+        }
+        self.offset = (self.offset + LINES - 1).min(max);
+        true
+    }
+
+    /// Show newer lines, `false` when already at the newest.
+    pub fn page_down(&mut self) -> bool {
+        if self.offset == 0 {
+            return false;
+        }
+        self.offset = self.offset.saturating_sub(LINES - 1);
+        if self.offset == 0 {
+            self.unseen = 0;
+        }
+        true
+    }
+
+    /// Go back to the newest line.
+    pub fn latest(&mut self) {
+        self.offset = 0;
+        self.unseen = 0;
+    }
+
+    /// Lines in the viewport, oldest first.
+    pub fn lines(&self) -> Vec<LogLine, LINES> {
+        let end = self.total() - self.offset;
+        let start = end.saturating_sub(LINES);
+        let mut lines = Vec::new();
+        let mut index = 0;
+        for message in self.messages.iter() {
+            // only the messages in view are wrapped
+            if index + message.lines > start && index < end {
+                let mut at = index;
+                message.wrap(|line| {
+                    if (start..end).contains(&at) {
+                        _ = lines.push(line);
+                    }
+                    at += 1;
+                });
+            }
+            index += message.lines;
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(text: &str, up: &str) -> Message {
+        let mut message = Message::default();
+        message.push_text(text);
+        _ = message.up.push_str(up);
+        message
+    }
+
+    fn repeat<const N: usize>(s: &str, n: usize) -> String<N> {
+        let mut out = String::new();
+        for _ in 0..n {
+            out.push_str(s).unwrap();
+        }
+        out
+    }
+
+    fn bodies(log: &LogView) -> Vec<String<42>, LINES> {
+        log.lines()
+            .iter()
+            .map(|line| line.body.as_str().into())
+            .collect()
+    }
+
+    #[test]
+    fn wrap_leaves_room_for_the_prefix() {
+        let mut lines: Vec<LogLine, 4> = Vec::new();
+        let text = "0123456789abcdefghijklmnopqrstuvwxyz";
+        message(text, "-12").wrap(|line| _ = lines.push(line));
+        // 3 small characters take the room of 2 big ones
+        assert_eq!(lines[0].body, text[..LINE_CHARS - 2]);
+        assert_eq!(lines[0].up, "-12");
+        assert_eq!(lines[1].body, text[LINE_CHARS - 2..]);
+        assert_eq!(lines[1].up, "");
+        assert_eq!(message(text, "-12").lines(), 2);
+        assert_eq!(message("a\nb\r\nc", "").lines(), 4);
+        assert_eq!(message("", "").lines(), 0);
+        assert_eq!(message("", "ok").lines(), 1);
+        // multibyte characters count once
+        assert_eq!(message(&repeat::<64>("é", LINE_CHARS), "").lines(), 1);
+    }
+
+    #[test]
+    fn long_text_is_cut() {
+        let long = repeat::<{ 2 * MAX_TEXT }>("x", 2 * MAX_TEXT);
+        let message = message(&long, "");
+        assert_eq!(message.text.len(), MAX_TEXT);
+        assert!(message.text.ends_with(CUT));
+        // not in the middle of a character
+        let long = repeat::<{ 2 * MAX_TEXT }>("é", MAX_TEXT);
+        let mut message = Message::default();
+        message.push_text("a");
+        message.push_text(&long);
+        assert!(message.text.ends_with("é..."));
+    }
+
+    #[test]
+    fn viewport_follows_the_newest_line() {
+        let mut log = LogView::new();
+        for i in 0..LINES as u8 + 2 {
+            log.push(message(core::str::from_utf8(&[b'a' + i]).unwrap(), ""));
+        }
+        assert_eq!(bodies(&log).first().unwrap(), "c");
+        assert_eq!(bodies(&log).last().unwrap(), "j");
+        assert!(!log.scrolled());
+
+        assert!(log.page_up());
+        assert_eq!(bodies(&log), ["a", "b", "c", "d", "e", "f", "g", "h"]);
+        assert!(!log.page_up());
+        // new messages keep the same lines in view
+        log.push(message("k", ""));
+        assert_eq!(bodies(&log)[0], "a");
+        assert_eq!(log.unseen(), 1);
+
+        assert!(log.page_down());
+        assert!(!log.page_down());
+        assert_eq!(log.unseen(), 0);
+        assert_eq!(bodies(&log).last().unwrap(), "k");
+    }
+
+    #[test]
+    fn oldest_messages_make_room() {
+        let mut log = LogView::new();
+        for _ in 0..MAX_LOG + 3 {
+            log.push(message("two lines two lines two lines", ""));
+        }
+        assert_eq!(log.total(), 2 * MAX_LOG);
+        while log.page_up() {}
+        assert_eq!(log.offset, 2 * MAX_LOG - LINES);
+    }
+
+    #[test]
+    fn delivery_rewraps_the_message() {
+        let mut log = LogView::new();
+        let mut own = message("me: exactly twenty-on", "");
+        own.id = Some(4);
+        log.push(own);
+        assert_eq!(log.total(), 1);
+        assert!(log.set_up(4, ".."));
+        assert_eq!(log.total(), 2);
+        assert_eq!(bodies(&log), ["me: exactly twenty-o", "n"]);
+        assert!(!log.set_up(5, "ok"));
+    }
+}
@@ -0,0 +1,159 @@
+//! Flood relaying between simulated nodes out of range of each other.
+
+use lora_rust::frame::{Address, BROADCAST, DEFAULT_TTL};
+use lora_rust::interface::Delivery;
+use lora_rust::sim::{run, Air, AirConfig, SimNode};
+
+/// Nodes `1..=count` in a line, each one only hearing its neighbours.
+fn chain(air: &Air, count: usize) -> Vec<SimNode> {
+    let nodes: Vec<SimNode> = (1..=count)
+        .map(|address| {
+            let mut node = SimNode::new(air, address as Address);
+            node.node.beacon_interval_ms = 0;
+            node.node.routes.advertise_at = 1_000_000;
+            node
+        })
+        .collect();
+    for (i, a) in nodes.iter().enumerate() {
+        for b in nodes.iter().skip(i + 2) {
+            air.set_link(a.radio.id(), b.radio.id(), false);
+        }
+    }
+    nodes
+}
+
+fn received(node: &SimNode) -> Vec<&[u8]> {
+    node.screen
+        .received
+        .iter()
+        .map(|(_, body)| &body[..])
+        .collect()
+}
+
+#[test]
+fn message_and_ack_are_relayed() {
+    let air = Air::new(AirConfig::default());
+    let mut nodes = chain(&air, 3);
+    nodes[0].send("over there");
+    run(&air, &mut nodes, 5_000);
+    assert_eq!(received(&nodes[2]), [b"over there"]);
+    assert_eq!(received(&nodes[1]), [b"over there"]);
+    assert_eq!(nodes[0].screen.delivery(1), Some(Delivery::Delivered));
+    assert!(nodes[1].node.mesh.relayed >= 2);
+}
+
+#[test]
+fn hop_limit_ends_the_flood() {
+    let air = Air::new(AirConfig::default());
+    let hops = DEFAULT_TTL as usize + 1;
+    let mut nodes = chain(&air, hops + 2);
+    for node in nodes.iter_mut() {
+        node.node.want_ack = false;
+    }
+    nodes[0].send("how far");
+    run(&air, &mut nodes, 10_000);
+    for node in &nodes[1..=hops] {
+        assert_eq!(received(node), [b"how far"]);
+    }
+    assert!(received(&nodes[hops + 1]).is_empty());
+    // the last one reached had no hop left to give
+    assert_eq!(nodes[hops].node.mesh.relayed, 0);
+}
+
+#[test]
+fn copies_are_shown_and_relayed_once() {
+    let air = Air::new(AirConfig::default());
+    // everyone in range, every relay is heard by all
+    let mut nodes = chain(&air, 4);
+    for a in nodes.iter() {
+        for b in nodes.iter() {
+            air.set_link(a.radio.id(), b.radio.id(), true);
+        }
+    }
+    for node in nodes.iter_mut() {
+        node.node.want_ack = false;
+    }
+    nodes[0].send("once");
+    run(&air, &mut nodes, 5_000);
+    for node in &nodes[1..] {
+        assert_eq!(received(node), [b"once"]);
+        assert!(node.node.mesh.relayed <= 1);
+    }
+    assert!(air.sent() <= nodes.len());
+}
+
+#[test]
+fn sealed_frames_are_relayed_without_the_key() {
+    let air = Air::new(AirConfig::default());
+    let mut nodes = chain(&air, 3);
+    nodes[0].node.key = Some([7; 32]);
+    nodes[2].node.key = Some([7; 32]);
+    nodes[0].send("secret");
+    run(&air, &mut nodes, 5_000);
+    assert_eq!(received(&nodes[2]), [b"secret"]);
+    assert!(received(&nodes[1]).is_empty());
+    assert_eq!(nodes[0].screen.delivery(1), Some(Delivery::Delivered));
+}
+
+#[test]
+fn addressed_message_is_shown_by_its_recipient_only() {
+    let air = Air::new(AirConfig::default());
+    let mut nodes = chain(&air, 3);
+    air.set_link(nodes[0].radio.id(), nodes[2].radio.id(), true);
+    nodes[0].send_to(2, "just you");
+    run(&air, &mut nodes, 5_000);
+    assert_eq!(received(&nodes[1]), [b"just you"]);
+    assert!(received(&nodes[2]).is_empty());
+    assert_eq!(nodes[0].screen.delivery(1), Some(Delivery::Delivered));
+}
+
+#[test]
+fn addressed_message_follows_the_learned_route() {
+    let air = Air::new(AirConfig::default());
+    let mut nodes = chain(&air, 3);
+    nodes[2].send("here I am");
+    run(&air, &mut nodes, 5_000);
+    assert_eq!(nodes[0].node.routes.next_hop(3), 2);
+    nodes[0].send_to(3, "got you");
+    run(&air, &mut nodes, 5_000);
+    assert_eq!(received(&nodes[2]), [b"got you"]);
+    assert_eq!(received(&nodes[1]), [b"here I am"]);
+    // its first sequence number went to acknowledging node 3
+    let own = &nodes[0].screen.own;
+    assert!(matches!(own[..], [(_, Delivery::Delivered)]));
+}
+
+#[test]
+fn frames_failing_authentication_teach_nothing() {
+    let air = Air::new(AirConfig::default());
+    let mut nodes = chain(&air, 3);
+    air.set_link(nodes[0].radio.id(), nodes[2].radio.id(), true);
+    nodes[0].node.key = Some([7; 32]);
+    nodes[1].node.key = Some([7; 32]);
+    nodes[2].node.key = Some([8; 32]);
+    nodes[2].send("let me in");
+    run(&air, &mut nodes, 5_000);
+    for node in &nodes[..2] {
+        assert!(received(node).is_empty());
+        assert_eq!(node.node.routes.next_hop(3), BROADCAST);
+        assert!(node.node.neighbours.iter().all(|n| n.address != 3));
+    }
+    // the relayed copy names node 2 as its last hop, it is not believed either
+    assert!(nodes[0].node.neighbours.iter().next().is_none());
+}
+
+#[test]
+fn forged_frames_are_not_relayed() {
+    let air = Air::new(AirConfig::default());
+    let mut nodes = chain(&air, 3);
+    nodes[0].node.key = Some([8; 32]);
+    nodes[1].node.key = Some([7; 32]);
+    nodes[2].node.key = Some([7; 32]);
+    nodes[0].node.want_ack = false;
+    nodes[0].send("flood this");
+    run(&air, &mut nodes, 5_000);
+    assert!(nodes[1].node.rejected > 0);
+    assert_eq!(nodes[1].node.mesh.relayed, 0);
+    assert_eq!(air.sent(), 1);
+    assert!(received(&nodes[2]).is_empty());
+}
@@ -0,0 +1,164 @@
+//! Screens rendered headless and compared with the images in `tests/golden`.
+//!
+//! After an intended change of the layout, write the images again with
+//! `UPDATE_GOLDEN=1 cargo test --test screens` and look at them before
+//! committing.
+
+use std::path::PathBuf;
+
+use lora_rust::framebuffer::Framebuffer;
+use lora_rust::interface::{Delivery, Interface, Oled128x128};
+use lora_rust::neighbours::Presence;
+use lora_rust::node::Node;
+use lora_rust::screen::Screen;
+
+fn golden(name: &str) -> PathBuf {
+    PathBuf::from(file!())
+        .with_file_name("golden")
+        .join(name)
+        .with_extension("pbm")
+}
+
+fn check(name: &str, fb: &Framebuffer) {
+    let path = golden(name);
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        let mut out = Vec::new();
+        fb.write_pbm(&mut out).unwrap();
+        std::fs::write(&path, out).unwrap();
+        return;
+    }
+    let data = std::fs::read(&path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+    let expected = Framebuffer::from_pbm(&data).expect("not a 128x128 PBM");
+    if *fb != expected {
+        let actual = path.with_extension("actual.pbm");
+        let mut out = Vec::new();
+        fb.write_pbm(&mut out).unwrap();
+        _ = std::fs::write(&actual, out);
+        panic!(
+            "{} differs by {} pixels, rendered to {}",
+            name,
+            fb.diff(&expected),
+            actual.display()
+        );
+    }
+}
+
+/// Render everything once, the cursor is not shown yet.
+fn render(screen: &mut Oled128x128) -> Framebuffer {
+    let mut fb = Framebuffer::new();
+    screen.invalidate();
+    screen.draw(&mut fb);
+    fb
+}
+
+fn chat() -> Oled128x128<'static> {
+    let mut screen = Oled128x128::new();
+    screen.set_title(b"LoRa 433.4");
+    screen.set_status("87%", "SF7");
+    screen.add_log(Some(b"ada"), b"hello there", Some(9), Some(-57));
+    screen.add_log(Some(b"bob"), b"far away", Some(-12), Some(-118));
+    screen.add_own(1, None, b"hi both", Delivery::Delivered);
+    screen.add_own(2, Some(b"ada"), b"still there?", Delivery::Pending);
+    screen.add_log(
+        Some(b"ada"),
+        b"a longer answer that takes more than one line of the screen",
+        Some(5),
+        Some(-80),
+    );
+    screen.set_input(b"typing", 6);
+    screen
+}
+
+#[test]
+fn log_with_signal_and_delivery() {
+    check("log", &render(&mut chat()));
+}
+
+#[test]
+fn log_without_signal() {
+    let mut screen = Oled128x128::new();
+    screen.set_signal(false);
+    screen.set_title(b"LoRa 433.4");
+    screen.add_log(Some(b"ada"), b"no prefix here", Some(9), Some(-57));
+    screen.add_log(None, b"from the keyboard", None, None);
+    check("log_no_signal", &render(&mut screen));
+}
+
+#[test]
+fn log_scrolled_up_counts_new_messages() {
+    let mut screen = chat();
+    for i in 0..6u8 {
+        screen.add_log(Some(b"bob"), &[b'0' + i; 4], Some(1), Some(-90));
+    }
+    assert!(screen.page_up());
+    screen.add_log(Some(b"ada"), b"unseen", Some(1), Some(-90));
+    check("log_scrolled", &render(&mut screen));
+}
+
+#[test]
+fn long_input_scrolls_around_the_cursor() {
+    let mut screen = chat();
+    let input = "the quick brown fox jumps over the lazy dog";
+    screen.set_input(input.as_bytes(), 20);
+    let mut fb = render(&mut screen);
+    // the cursor shows from the 30th frame
+    for _ in 1..30 {
+        screen.draw(&mut fb);
+    }
+    check("long_input", &fb);
+}
+
+#[test]
+fn latin1_input() {
+    let mut screen = chat();
+    screen.set_input("déjà vu à côté".as_bytes(), 3);
+    check("latin1_input", &render(&mut screen));
+}
+
+#[test]
+fn overlay_over_the_log() {
+    let mut screen = chat();
+    screen.set_overlay(Some("Sending..."));
+    check("overlay", &render(&mut screen));
+}
+
+#[test]
+fn settings_menu() {
+    let node = Node::new(0x12ab);
+    let mut screen = chat();
+    screen.open_menu(&node);
+    check("menu", &render(&mut screen));
+}
+
+fn presence(name: &str, battery: Option<u8>) -> Presence {
+    let mut presence = Presence {
+        battery,
+        ..Default::default()
+    };
+    presence.name.push_str(name).unwrap();
+    presence
+}
+
+#[test]
+fn neighbour_page() {
+    let mut node = Node::new(0x12ab);
+    let now = 600_000;
+    node.neighbours.heard(0x0001, -57, Some(9), now - 4_000);
+    node.neighbours
+        .announced(0x0001, presence("ada", Some(100)));
+    node.neighbours.heard(0x0002, -118, Some(-12), now - 95_000);
+    node.neighbours
+        .announced(0x0002, presence("bartholomew", None));
+    node.neighbours.heard(0xbeef, -101, None, now - 599_000);
+    let mut screen = chat();
+    screen.set_page(Screen::Neighbours.render(&node, now));
+    check("neighbours", &render(&mut screen));
+}
+
+#[test]
+fn nobody_in_range() {
+    let node = Node::new(0x12ab);
+    let mut screen = chat();
+    screen.set_page(Screen::Neighbours.render(&node, 0));
+    check("neighbours_empty", &render(&mut screen));
+}